@@ -1,156 +1,4016 @@
+use std::rc::Rc;
+
+use js_sys::{Array, Date, Uint8Array};
 use serde::{Deserialize, Serialize};
+use wasm_bindgen::{closure::Closure, JsCast, JsValue};
+use wasm_bindgen_futures::spawn_local;
 use web_sys::HtmlInputElement;
+use web_sys::{window, Blob, HtmlElement, Url};
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged};
+use yew_router::history::History;
+use yew_router::scope_ext::RouterScopeExt;
 
 use crate::services::event_bus::EventBus;
-use crate::{services::websocket::WebsocketService, User};
+use crate::services::protocol::{
+    default_room, parse_upgrade_required, BusMessage, Delivery, HandshakeOutcome, MsgTypes, WebSocketMessage,
+    PROTOCOL_VERSION,
+};
+use crate::{
+    services::{
+        config,
+        websocket::{ConnectionMetrics, ConnectionState, WebsocketService},
+    },
+    Route, User, WsHandle,
+};
 
 pub enum Msg {
-    HandleMsg(String),
+    /// An event off the `EventBus`, already parsed once into a typed
+    /// [`BusMessage`] by `WebsocketService` — this match only decodes each
+    /// `message_type`'s own `data` payload, never the envelope itself.
+    HandleMsg(Delivery),
     SubmitMessage,
+    InputChanged,
+    PruneTypingIndicators,
+    StartEdit(String),
+    /// Leaves edit mode without sending anything — the "✕" next to the
+    /// editing banner, or `Escape` pressed in the chat input.
+    CancelEdit,
+    DeleteMessage(String),
+    ToggleReaction(String, String),
+    DismissNotice(String),
+    StartReply(String),
+    CancelReply,
+    ScrollToMessage(String),
+    /// Cycles [`Chat::ephemeral_ttl`] between `None` and `Some(30)` — the ⏱
+    /// button next to the send button.
+    ToggleEphemeral,
+    /// The 🚪 button in the header — tears down the session and sends the
+    /// user back to [`Route::Login`].
+    Logout,
+    /// The "Pin" button in a message's hover menu.
+    PinMessage(String),
+    /// The "Unpin" button, either in a message's hover menu or next to an
+    /// entry in the "📌 Pinned" strip.
+    UnpinMessage(String),
+    /// The "📌 Pinned" header button — shows or hides the strip.
+    TogglePinnedStrip,
+    MessageAcked(String),
+    MessageAckFailed(String, String),
+    SwitchRoom(String),
+    OpenDm(String),
+    CloseDm(String),
+    ShowRoom,
+    /// Fired on every `visibilitychange` event, both hiding and showing.
+    VisibilityChanged,
+    /// Toggles [`Chat::sound_enabled`] and persists the new value.
+    ToggleSound,
+    /// Toggles [`Chat::theme`] and persists the new value.
+    ToggleTheme,
+    /// The "try again" button on the full-pane error shown for
+    /// `ConnectionState::Failed`.
+    RetryConnection,
+    /// Fired on the hidden file input's `change` event once a user picks an
+    /// image to attach.
+    AttachImage,
+    /// The `FileReader` for an attached image finished reading it into a
+    /// `data:image/...;base64,...` URL.
+    ImageDataReady(String),
+    /// Toggles the emoji picker open/closed.
+    ToggleEmojiPicker,
+    /// The full-screen overlay behind an open picker was clicked, or
+    /// `Escape` was pressed while it had focus.
+    CloseEmojiPicker,
+    /// An emoji was picked, either by clicking it or pressing `Enter` while
+    /// it's keyboard-focused; inserted into `chat_input` at the cursor.
+    InsertEmoji(String),
+    /// A key was pressed while the picker grid has focus, for arrow-key
+    /// navigation between emojis.
+    EmojiPickerKey(KeyboardEvent),
+    /// Toggles the search box open/closed; closing also clears the query.
+    ToggleSearch,
+    /// Fired on the search box's `input` event.
+    SearchQueryChanged(String),
+    /// `Enter` pressed in the search box — jumps to the next match,
+    /// wrapping back to the first once the last is passed.
+    SearchNext,
+    /// Fired on the message pane's `scroll` event; reports the last visible
+    /// message as read once throttled and de-duplicated.
+    MessagesScrolled,
+    /// The floating "jump to latest" button — scrolls straight to the
+    /// bottom of the pane and clears `missed_while_scrolled`.
+    JumpToLatest,
+    /// The "retry" link on a message whose ack failed — resends it with the
+    /// same id so the server (and any client that already has it) treats it
+    /// as the same message rather than a duplicate.
+    RetrySend(String),
+    /// The "N replies" link under a root message — opens (or switches to)
+    /// that message's thread panel.
+    OpenThread(String),
+    /// Closes the open thread panel.
+    CloseThread,
+    /// The send button (or `Enter`) in the open thread panel's own input.
+    SubmitThreadMessage,
+    /// A pointer or keyboard event landed on `document` — resets the idle
+    /// clock and, if we'd gone `Away`, reports `Online` again.
+    UserActivity,
+    /// Periodic tick checking whether we've gone idle long enough to report
+    /// `Away`, and pruning roster entries that have been `Offline` past
+    /// [`OFFLINE_RETENTION_MS`].
+    PresenceTick,
 }
 
-#[derive(Deserialize)]
+/// Accepts `timestamp` as either epoch millis (what this client sends) or
+/// an RFC3339 string (what some servers log instead), normalizing both to
+/// millis via `js_sys::Date::parse` rather than pulling in a date-parsing
+/// crate for one field. An RFC3339 string that fails to parse is dropped
+/// to `None` rather than failing the whole message.
+fn deserialize_timestamp<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum RawTimestamp {
+        Millis(f64),
+        Rfc3339(String),
+    }
+    match Option::<RawTimestamp>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(RawTimestamp::Millis(millis)) => Ok(Some(millis)),
+        Some(RawTimestamp::Rfc3339(s)) => {
+            let millis = Date::parse(&s);
+            Ok(if millis.is_nan() { None } else { Some(millis) })
+        }
+    }
+}
+
+/// Delivery state of an outgoing message, shown as a small glyph in the
+/// corner of your own bubbles. `Read` is never stored here — it's derived
+/// at render time from [`readers_of`] once someone's `read_up_to` reaches
+/// this message — since it would otherwise mean rewriting every message's
+/// status on every incoming `MsgTypes::Read`. This server has no separate
+/// "delivered to the recipient's device" signal beyond the broadcast the
+/// ack already confirms, so `Sent` is promoted straight to `Delivered`
+/// rather than leaving a state nothing will ever move it out of.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MessageStatus {
+    Sending,
+    Sent,
+    Delivered,
+    Read,
+}
+
+impl Default for MessageStatus {
+    /// A message this client didn't just submit itself — restored from
+    /// history, or received from someone else — is already at rest.
+    fn default() -> Self {
+        MessageStatus::Delivered
+    }
+}
+
+/// A structured attachment on a message, distinguished from a bare pasted
+/// link (see [`is_inline_image_url`]) by carrying its own metadata instead
+/// of needing the text sniffed for a recognizable extension. New kinds of
+/// attachment (audio, video previews, ...) are just a new variant here —
+/// they don't need a new `MsgTypes` frame, since they still ride the
+/// ordinary `Message` frame's `attachments` field.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum Attachment {
+    Image { url: String, alt: String },
+    File { url: String, name: String, size: u64 },
+    Link { url: String, title: String },
+}
+
+#[derive(Serialize, Deserialize)]
 struct MessageData {
+    id: Option<String>,
     from: String,
     message: String,
+    /// Structured attachments riding alongside `message`'s text. Defaulted
+    /// so a message from a peer that predates this field (or one replayed
+    /// from history saved before it existed) still deserializes as a
+    /// plain text-only message instead of failing to parse.
+    #[serde(default)]
+    attachments: Vec<Attachment>,
+    #[serde(default, deserialize_with = "deserialize_timestamp")]
+    timestamp: Option<f64>,
+    #[serde(default)]
+    pending: bool,
+    /// Set once the server has confirmed it never saw this message (ack
+    /// timed out or the connection dropped first); `pending` is cleared
+    /// either way once the outcome is known.
+    #[serde(default)]
+    failed: bool,
+    /// Delivery state shown as a glyph on your own messages; see
+    /// [`MessageStatus`].
+    #[serde(default)]
+    status: MessageStatus,
+    #[serde(default)]
+    edited: bool,
+    #[serde(default)]
+    deleted: bool,
+    /// Whether this message is pinned to the room's "📌 Pinned" strip. Kept
+    /// in sync with [`Chat::pinned`], which is what the strip actually
+    /// renders from — this flag just drives the hover menu's "Pin"/"Unpin"
+    /// label and a highlight on the message itself.
+    #[serde(default)]
+    pinned: bool,
+    /// Emoji reactions on this message, keyed by emoji with the usernames
+    /// who reacted with it.
+    #[serde(default)]
+    reactions: std::collections::HashMap<String, Vec<String>>,
+    /// Id of the message this one is quoting, if it was sent as a reply.
+    #[serde(default)]
+    reply_to: Option<String>,
+    /// "Sender: snippet" of the quoted message, captured by the sender at
+    /// reply time — rendered in place of a local lookup against `reply_to`
+    /// when that id isn't in our own history. See
+    /// [`WebSocketMessage::reply_snippet`].
+    #[serde(default)]
+    reply_snippet: Option<String>,
+    /// Id of the root message this one is a thread reply to, if any — kept
+    /// out of `messages`/`dm_messages` entirely and grouped under
+    /// [`Chat::thread_replies`] instead of rendering inline.
+    #[serde(default)]
+    thread_root: Option<String>,
+    /// Username this message is a private direct message to, or `None` for
+    /// an ordinary message posted to `room`.
+    #[serde(default)]
+    to: Option<String>,
+    /// Usernames this message was whispered to, on top of `room`'s other
+    /// members — empty for an ordinary public message. Distinct from `to`:
+    /// a whisper stays in the room's message list (so its surrounding
+    /// context is visible) but is rendered with a dashed border and a "only
+    /// visible to ..." caption, and [`Chat::is_search_match`] excludes it
+    /// from search for anyone who wasn't a sender or recipient.
+    #[serde(default)]
+    recipients: Vec<String>,
+    /// Seconds after which this message should disappear, set by the sender
+    /// via the ⏱ toggle or a `/tmp <seconds> text` command. `None` for an
+    /// ordinary message that never expires.
+    #[serde(default)]
+    expires_in: Option<u64>,
+    /// Wall-clock time (matching `Date::now()`) this message should be
+    /// pruned at, computed locally from `expires_in` the moment *this*
+    /// client received it — deliberately never (de)serialized, so two
+    /// clients with skewed system clocks don't disagree about when a
+    /// message sent on one expires on the other; each starts its own
+    /// countdown from its own receive time.
+    #[serde(skip)]
+    expires_at: Option<f64>,
+    /// Channel this message was posted in.
+    #[serde(default = "default_room")]
+    room: String,
+    /// Object URL for a received image, set locally after decoding an
+    /// `Image` frame; never (de)serialized from the wire.
+    #[serde(skip)]
+    image_url: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-#[serde(rename_all = "lowercase")]
-pub enum MsgTypes {
-    Users,
-    Register,
-    Message,
+/// A centered, muted notice synthesized client-side from a `MsgTypes::Join`
+/// or `MsgTypes::Leave` frame — "alice joined" / "bob left" — rather than an
+/// ordinary chat message, so it renders without an avatar and doesn't
+/// participate in search, editing, or reactions. Kept in its own per-room
+/// vec (see [`Chat::system_events`]) instead of folded into `MessageData` so
+/// that distinction doesn't need every message-handling code path to branch
+/// on "is this actually a message".
+struct SystemEvent {
+    text: String,
+    timestamp: f64,
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct WebSocketMessage {
-    message_type: MsgTypes,
-    data_array: Option<Vec<String>>,
-    data: Option<String>,
+/// How soon a second join/leave from the same user has to follow the last
+/// one to update it in place rather than adding a new line — someone's
+/// connection flapping shouldn't spam the room with alternating "joined" /
+/// "left" notices.
+const SYSTEM_EVENT_COALESCE_MS: f64 = 30_000.0;
+
+/// The emoji reactions offered on every message.
+const REACTION_EMOJIS: [&str; 3] = ["👍", "❤️", "😂"];
+
+/// Payload carried by `MsgTypes::React`'s `data` field: which emoji, and
+/// who's toggling it.
+#[derive(Clone, Serialize, Deserialize)]
+struct ReactionPayload {
+    emoji: String,
+    user: String,
+}
+
+/// Payload carried by `MsgTypes::Pin`'s `data` field: a snippet of the
+/// pinned message, so a client without it in local history can still render
+/// the "📌 Pinned" strip entry. `MsgTypes::Unpin` carries no payload — `id`
+/// alone is enough to remove an entry.
+#[derive(Clone, Serialize, Deserialize)]
+struct PinPayload {
+    snippet: String,
+}
+
+/// An entry in [`Chat::pinned`] — just enough to render the "📌 Pinned"
+/// strip without needing the full message to still be in local history.
+#[derive(Clone, PartialEq)]
+struct PinnedMessage {
+    id: String,
+    snippet: String,
+}
+
+/// Payload carried by `MsgTypes::Presence`'s `data` field: who, and their
+/// new status.
+#[derive(Clone, Serialize, Deserialize)]
+struct PresencePayload {
+    user: String,
+    status: UserStatus,
+}
+
+/// Payload carried by `MsgTypes::Error`'s `data` field: a machine-readable
+/// `code` ("name_taken", "message_too_long", "rate_limited", ...), a
+/// human-readable `message` to toast, and the outgoing message id (if any)
+/// the rejected action was about.
+#[derive(Clone, Serialize, Deserialize)]
+struct ServerErrorPayload {
+    code: String,
+    message: String,
+    #[serde(default)]
+    ref_id: Option<String>,
+}
+
+/// Toggles `user` in or out of `emoji`'s reaction list on the message with
+/// `id`, dropping the emoji entry entirely once its list is empty. Returns
+/// whether a matching message was found.
+fn toggle_reaction(messages: &mut [MessageData], id: &str, emoji: &str, user: &str) -> bool {
+    let Some(m) = messages.iter_mut().find(|m| m.id.as_deref() == Some(id)) else {
+        return false;
+    };
+    let users = m.reactions.entry(emoji.to_string()).or_default();
+    if let Some(pos) = users.iter().position(|u| u == user) {
+        users.remove(pos);
+        if users.is_empty() {
+            m.reactions.remove(emoji);
+        }
+    } else {
+        users.push(user.to_string());
+    }
+    true
+}
+
+/// Longest quoted-message body before [`reply_snippet_for`] shortens it with
+/// an ellipsis.
+const REPLY_SNIPPET_LIMIT: usize = 80;
+
+/// Builds the "sender: first ~80 chars" text embedded in an outgoing reply's
+/// `reply_snippet`, so a recipient without `original` in their own history
+/// can still show something for the quote.
+fn reply_snippet_for(original: &MessageData) -> String {
+    let body = if original.message.chars().count() > REPLY_SNIPPET_LIMIT {
+        let mut s: String = original.message.chars().take(REPLY_SNIPPET_LIMIT).collect();
+        s.push('…');
+        s
+    } else {
+        original.message.clone()
+    };
+    format!("{}: {}", original.from, body)
+}
+
+/// How long a reaction for a message id we haven't seen yet is kept around,
+/// waiting for that message to show up — a reaction can outrace the message
+/// it targets the same way a `Read` or `Delete` can, but unlike those it's
+/// low enough stakes that giving up after a short wait beats holding onto it
+/// forever.
+const REACTION_BUFFER_MS: f64 = 5_000.0;
+
+/// Applies any buffered reactions targeting `id` now that it's arrived, and
+/// separately drops whatever's aged out of [`REACTION_BUFFER_MS`] — called
+/// once per incoming message rather than on a timer, since that's the only
+/// time the answer to "has this id shown up yet" can change.
+fn apply_pending_reactions(pending: &mut Vec<(String, ReactionPayload, f64)>, messages: &mut [MessageData], id: &str, now: f64) {
+    pending.retain(|(pending_id, payload, queued_at)| {
+        if pending_id == id {
+            toggle_reaction(messages, id, &payload.emoji, &payload.user);
+            return false;
+        }
+        now - queued_at <= REACTION_BUFFER_MS
+    });
+}
+
+/// Whether `text` contains an `@name` token addressed at `me`, ignoring
+/// trailing punctuation like `@bob,` or `@bob!` and matching
+/// case-insensitively so `@Bob` still counts as a mention of `bob`.
+/// Requiring the token to start a whitespace-separated word (rather than
+/// just scanning for `@me` anywhere) is what keeps an email address like
+/// `bob@example.com` from triggering a false mention.
+fn mentions(text: &str, me: &str) -> bool {
+    text.split_whitespace().any(|word| {
+        word.strip_prefix('@')
+            .map(|name| name.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_').eq_ignore_ascii_case(me))
+            .unwrap_or(false)
+    })
+}
+
+/// Parses a `/whisper @alice @bob message text` input into the whispered
+/// recipients and the remaining message text, or `None` if `input` isn't a
+/// `/whisper` command. Recipient `@name` tokens are consumed greedily from
+/// the front; the first word that isn't one ends the recipient list and
+/// starts the message text. `None` is also returned for `/whisper` with no
+/// recipients or no message text, so the caller falls back to sending the
+/// input as an ordinary message rather than silently eating it.
+fn parse_whisper(input: &str) -> Option<(Vec<String>, String)> {
+    let rest = input.strip_prefix("/whisper ")?;
+    let mut words = rest.split_whitespace().peekable();
+    let mut recipients = Vec::new();
+    while let Some(word) = words.peek() {
+        match word.strip_prefix('@') {
+            Some(name) if !name.is_empty() => {
+                recipients.push(name.to_string());
+                words.next();
+            }
+            _ => break,
+        }
+    }
+    let message = words.collect::<Vec<_>>().join(" ");
+    if recipients.is_empty() || message.is_empty() {
+        None
+    } else {
+        Some((recipients, message))
+    }
+}
+
+/// Parses a `/tmp 30 message text` input into the requested TTL in seconds
+/// and the remaining message text, or `None` if `input` isn't a `/tmp`
+/// command, the leading token after it isn't a valid number, or there's no
+/// message text left — the caller falls back to sending the input as an
+/// ordinary message in that case, mirroring [`parse_whisper`].
+fn parse_ephemeral(input: &str) -> Option<(u64, String)> {
+    let rest = input.strip_prefix("/tmp ")?;
+    let mut words = rest.split_whitespace();
+    let seconds: u64 = words.next()?.parse().ok()?;
+    let message = words.collect::<Vec<_>>().join(" ");
+    if message.is_empty() {
+        None
+    } else {
+        Some((seconds, message))
+    }
+}
+
+/// The classic shrug, appended by a `/shrug` command.
+const SHRUG: &str = "¯\\_(ツ)_/¯";
+
+/// A leading slash command recognized in the chat input, parsed by
+/// [`parse_command`] and handled locally in `Msg::SubmitMessage` before
+/// [`parse_whisper`] or [`parse_ephemeral`] ever see the text — none of
+/// these reach the wire as their literal command syntax.
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// `/me <action>` — sent as an italicized third-person action rather
+    /// than an ordinary message body.
+    Me(String),
+    /// `/shrug`, with or without leading text to append it to.
+    Shrug(String),
+    /// `/clear` — wipes the locally rendered history for the current room
+    /// or DM without telling the server; everyone else's view is untouched.
+    Clear,
+    /// Starts with `/` but isn't a recognized command (and isn't
+    /// `/whisper` or `/tmp`, parsed separately) — shown as an ephemeral
+    /// hint instead of being sent.
+    Unknown(String),
+    /// Not a command at all — `input` should be sent as an ordinary
+    /// message.
+    None,
+}
+
+/// Parses `input` for a leading slash command. `/me` requires a non-empty
+/// action and `/clear` must be the whole input; anything else starting with
+/// `/` that isn't `/whisper` or `/tmp` (handled by their own parsers further
+/// down the pipeline) falls through to [`Command::Unknown`].
+fn parse_command(input: &str) -> Command {
+    if let Some(action) = input.strip_prefix("/me ") {
+        let action = action.trim();
+        return if action.is_empty() { Command::Unknown(input.to_string()) } else { Command::Me(action.to_string()) };
+    }
+    if input == "/shrug" {
+        return Command::Shrug(String::new());
+    }
+    if let Some(prefix) = input.strip_prefix("/shrug ") {
+        return Command::Shrug(prefix.trim().to_string());
+    }
+    if input == "/clear" {
+        return Command::Clear;
+    }
+    if input.starts_with('/') && !input.starts_with("/whisper") && !input.starts_with("/tmp") {
+        return Command::Unknown(input.to_string());
+    }
+    Command::None
+}
+
+/// Retains only the messages in `messages` that haven't yet expired as of
+/// `now` (matching `Date::now()`), returning whether anything was removed so
+/// the caller knows whether a re-render is warranted.
+fn prune_expired(messages: &mut Vec<MessageData>, now: f64) -> bool {
+    let before = messages.len();
+    messages.retain(|m| m.expires_at.map_or(true, |at| now < at));
+    messages.len() != before
+}
+
+/// The document title outside of the "(N) unread" badge and the temporary
+/// mention flash from [`flash_title`].
+const APP_TITLE: &str = "YewChat";
+
+/// Whether the tab is currently in the background — used to decide whether
+/// an incoming message should bump [`Chat::unread`] and the title badge.
+fn document_hidden() -> bool {
+    window()
+        .and_then(|w| w.document())
+        .map(|d| d.hidden())
+        .unwrap_or(false)
+}
+
+/// Sets the document title to `"(N) YewChat"`, or back to [`APP_TITLE`]
+/// once `unread` returns to zero.
+fn set_unread_title(unread: usize) {
+    let Some(document) = window().and_then(|w| w.document()) else {
+        return;
+    };
+    if unread > 0 {
+        document.set_title(&format!("({}) {}", unread, APP_TITLE));
+    } else {
+        document.set_title(APP_TITLE);
+    }
+}
+
+/// Longest message body shown in a desktop notification before truncating
+/// with an ellipsis.
+const NOTIFICATION_BODY_LIMIT: usize = 120;
+
+/// Shows a native notification for a message from `from`, truncating `body`
+/// to `NOTIFICATION_BODY_LIMIT` chars. Clicking it focuses this window,
+/// since that's the whole point of switching to the tab in response. No-ops
+/// (including on unsupported browsers) rather than erroring — notifications
+/// are a nice-to-have, not a message-delivery guarantee.
+fn show_notification(from: &str, body: &str) {
+    let truncated = if body.chars().count() > NOTIFICATION_BODY_LIMIT {
+        let mut s: String = body.chars().take(NOTIFICATION_BODY_LIMIT).collect();
+        s.push('…');
+        s
+    } else {
+        body.to_string()
+    };
+    let mut options = web_sys::NotificationOptions::new();
+    options.body(&truncated);
+    let notification = match web_sys::Notification::new_with_options(from, &options) {
+        Ok(n) => n,
+        Err(e) => {
+            log::warn!("failed to show notification: {:?}", e);
+            return;
+        }
+    };
+    let onclick = Closure::<dyn Fn()>::new(|| {
+        if let Some(window) = window() {
+            let _ = window.focus();
+        }
+    });
+    notification.set_onclick(Some(onclick.as_ref().unchecked_ref()));
+    onclick.forget();
+}
+
+/// `localStorage` key the mute toggle is persisted under.
+const SOUND_ENABLED_STORAGE_KEY: &str = "sound_enabled";
+
+/// `localStorage` key the theme toggle is persisted under.
+const THEME_STORAGE_KEY: &str = "theme";
+
+/// Shortest gap between two played message sounds, so a burst of messages
+/// arriving together plays one "ding" instead of an overlapping pile of
+/// them.
+const SOUND_DEBOUNCE_MS: f64 = 1_000.0;
+
+/// Reads the persisted mute setting, defaulting to enabled if unset or on
+/// browsers without `localStorage`.
+fn load_sound_enabled() -> bool {
+    window()
+        .and_then(|w| w.local_storage().ok())
+        .flatten()
+        .and_then(|storage| storage.get_item(SOUND_ENABLED_STORAGE_KEY).ok())
+        .flatten()
+        .map(|v| v != "false")
+        .unwrap_or(true)
+}
+
+/// Persists the mute setting so it survives a reload.
+fn save_sound_enabled(enabled: bool) {
+    if let Some(storage) = window().and_then(|w| w.local_storage().ok()).flatten() {
+        let _ = storage.set_item(SOUND_ENABLED_STORAGE_KEY, if enabled { "true" } else { "false" });
+    }
+}
+
+/// Plays the bundled notification sound, unless one already played within
+/// [`SOUND_DEBOUNCE_MS`]. Returns the new `last_played_at` to store back on
+/// `Chat::last_sound_at` regardless of whether it actually played, so the
+/// debounce window is measured from the first message of a burst rather
+/// than sliding forward on every message in it.
+fn play_message_sound(last_played_at: f64) -> f64 {
+    let now = Date::now();
+    if now - last_played_at < SOUND_DEBOUNCE_MS {
+        return last_played_at;
+    }
+    match web_sys::HtmlAudioElement::new_with_src("sound/notify.wav") {
+        Ok(audio) => {
+            let play = audio.play();
+            if let Err(e) = play {
+                log::warn!("failed to play notification sound: {:?}", e);
+            }
+        }
+        Err(e) => log::warn!("failed to create notification sound: {:?}", e),
+    }
+    now
+}
+
+/// The color scheme `view` renders in, toggled from the header and
+/// persisted across reloads under [`THEME_STORAGE_KEY`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Theme {
+    Light,
+    Dark,
+}
+
+impl Theme {
+    fn toggled(self) -> Self {
+        match self {
+            Theme::Light => Theme::Dark,
+            Theme::Dark => Theme::Light,
+        }
+    }
+
+    /// The Tailwind classes for each themed surface in `view`, gathered here
+    /// instead of an `if self.theme == Theme::Dark { ... } else { ... }` at
+    /// every call site that uses one.
+    fn classes(self) -> ThemeClasses {
+        match self {
+            Theme::Light => ThemeClasses {
+                channels_bg: "bg-blue-950",
+                channels_active: "bg-blue-700",
+                channels_hover: "hover:bg-blue-800",
+                users_bg: "bg-blue-900",
+                user_card_bg: "bg-blue-100",
+                user_card_hover: "hover:bg-blue-200",
+                user_status_text: "text-blue-900",
+                main_bg: "bg-blue-50",
+                header_bg: "bg-blue-200",
+                header_border: "border-blue-300",
+                header_title_text: "text-gray-800",
+                status_text: "text-gray-600",
+                tabs_bg: "bg-blue-100",
+                tabs_border: "border-blue-300",
+                input_bar_bg: "bg-white",
+                input_field_bg: "bg-gray-200",
+            },
+            Theme::Dark => ThemeClasses {
+                channels_bg: "bg-gray-950",
+                channels_active: "bg-gray-700",
+                channels_hover: "hover:bg-gray-800",
+                users_bg: "bg-gray-800",
+                user_card_bg: "bg-gray-700",
+                user_card_hover: "hover:bg-gray-600",
+                user_status_text: "text-gray-300",
+                main_bg: "bg-gray-900",
+                header_bg: "bg-gray-800",
+                header_border: "border-gray-700",
+                header_title_text: "text-gray-100",
+                status_text: "text-gray-400",
+                tabs_bg: "bg-gray-800",
+                tabs_border: "border-gray-700",
+                input_bar_bg: "bg-gray-800",
+                input_field_bg: "bg-gray-700",
+            },
+        }
+    }
+}
+
+/// Class set returned by [`Theme::classes`], one field per themed surface in
+/// `view`.
+struct ThemeClasses {
+    channels_bg: &'static str,
+    channels_active: &'static str,
+    channels_hover: &'static str,
+    users_bg: &'static str,
+    user_card_bg: &'static str,
+    user_card_hover: &'static str,
+    user_status_text: &'static str,
+    main_bg: &'static str,
+    header_bg: &'static str,
+    header_border: &'static str,
+    header_title_text: &'static str,
+    status_text: &'static str,
+    tabs_bg: &'static str,
+    tabs_border: &'static str,
+    input_bar_bg: &'static str,
+    input_field_bg: &'static str,
+}
+
+fn load_theme() -> Theme {
+    window()
+        .and_then(|w| w.local_storage().ok())
+        .flatten()
+        .and_then(|storage| storage.get_item(THEME_STORAGE_KEY).ok())
+        .flatten()
+        .map(|v| if v == "dark" { Theme::Dark } else { Theme::Light })
+        .unwrap_or(Theme::Light)
+}
+
+/// Persists the theme choice so it survives a reload.
+fn save_theme(theme: Theme) {
+    if let Some(storage) = window().and_then(|w| w.local_storage().ok()).flatten() {
+        let _ = storage.set_item(THEME_STORAGE_KEY, if theme == Theme::Dark { "dark" } else { "light" });
+    }
+}
+
+/// `localStorage` key the "recently used" emoji list is persisted under.
+const RECENT_EMOJIS_STORAGE_KEY: &str = "recent_emojis";
+
+/// How many emojis the "recently used" row keeps.
+const MAX_RECENT_EMOJIS: usize = 8;
+
+/// Emojis offered by the picker grid, most to least common first so the
+/// picker opens with a reasonable default keyboard focus.
+const EMOJI_GRID: [&str; 40] = [
+    "😀", "😂", "😍", "😊", "😉", "😢", "😭", "😡", "👍", "👎", "👏", "🙏", "🎉", "🔥", "💯", "❤️", "💔", "😴", "🤔",
+    "😎", "😱", "🤗", "🥳", "😅", "🙄", "😇", "🤝", "👀", "✅", "❌", "⭐", "💡", "🚀", "🍕", "☕", "🎵", "📷", "🐱",
+    "🐶", "🌸",
+];
+
+/// Number of columns `EMOJI_GRID` is laid out in, used by the picker's
+/// arrow-key navigation to move up/down a row instead of just left/right.
+const EMOJI_GRID_COLUMNS: usize = 8;
+
+/// Reads the persisted "recently used" emoji list, defaulting to empty if
+/// unset, unparseable, or on browsers without `localStorage`.
+fn load_recent_emojis() -> Vec<String> {
+    window()
+        .and_then(|w| w.local_storage().ok())
+        .flatten()
+        .and_then(|storage| storage.get_item(RECENT_EMOJIS_STORAGE_KEY).ok())
+        .flatten()
+        .and_then(|v| serde_json::from_str(&v).ok())
+        .unwrap_or_default()
+}
+
+/// Persists the "recently used" emoji list so it survives a reload.
+fn save_recent_emojis(recent: &[String]) {
+    if let Some(storage) = window().and_then(|w| w.local_storage().ok()).flatten() {
+        if let Ok(json) = serde_json::to_string(recent) {
+            let _ = storage.set_item(RECENT_EMOJIS_STORAGE_KEY, &json);
+        }
+    }
+}
+
+/// Moves `emoji` to the front of `recent`, adding it if it wasn't already
+/// there, and drops anything past [`MAX_RECENT_EMOJIS`].
+fn remember_emoji(recent: &mut Vec<String>, emoji: &str) {
+    recent.retain(|e| e != emoji);
+    recent.insert(0, emoji.to_string());
+    recent.truncate(MAX_RECENT_EMOJIS);
+}
+
+/// `localStorage` key chat history is persisted under.
+const MESSAGE_HISTORY_STORAGE_KEY: &str = "message_history";
+
+/// Key used to dedupe an incoming message against `Chat::seen_message_ids`:
+/// the message's own `id` when the server sent one, or else a
+/// sender+content fallback so a server running the old, id-less protocol
+/// still gets the same replay protection instead of none at all. The
+/// fallback can't tell two genuinely identical messages sent seconds apart
+/// by the same user apart from a replay of the first — an acceptable
+/// tradeoff for a server that predates `id` entirely.
+fn dedupe_key(message: &MessageData) -> String {
+    match &message.id {
+        Some(id) => id.clone(),
+        None => format!("{}\u{1e}{}", message.from, message.message),
+    }
+}
+
+/// How many of the most recent messages `save_message_history` keeps —
+/// unbounded history would grow `localStorage` forever across a long-lived
+/// browser profile.
+const MESSAGE_HISTORY_LIMIT: usize = 200;
+
+/// Reads the persisted message history, defaulting to empty if unset,
+/// unparseable, or on browsers without `localStorage` — restored in
+/// `Chat::create`, before the first render, so history is visible
+/// immediately instead of popping in.
+fn load_message_history() -> std::collections::HashMap<String, Vec<MessageData>> {
+    window()
+        .and_then(|w| w.local_storage().ok())
+        .flatten()
+        .and_then(|storage| storage.get_item(MESSAGE_HISTORY_STORAGE_KEY).ok())
+        .flatten()
+        .and_then(|v| serde_json::from_str(&v).ok())
+        .unwrap_or_default()
+}
+
+/// Persists the last [`MESSAGE_HISTORY_LIMIT`] messages of every room so a
+/// reload doesn't lose the conversation. Only ever called with
+/// `self.messages` — the public room history — not `self.dm_messages`;
+/// each room is trimmed independently so one busy room can't crowd another,
+/// quieter room's history out of what gets saved.
+fn save_message_history(messages: &std::collections::HashMap<String, Vec<MessageData>>) {
+    let Some(storage) = window().and_then(|w| w.local_storage().ok()).flatten() else {
+        return;
+    };
+    let trimmed: std::collections::HashMap<&String, &[MessageData]> = messages
+        .iter()
+        .map(|(room, history)| (room, &history[history.len().saturating_sub(MESSAGE_HISTORY_LIMIT)..]))
+        .collect();
+    match serde_json::to_string(&trimmed) {
+        Ok(json) => {
+            let _ = storage.set_item(MESSAGE_HISTORY_STORAGE_KEY, &json);
+        }
+        Err(e) => log::warn!("failed to persist message history: {}", e),
+    }
+}
+
+/// Removes the persisted message history — called on logout so the next
+/// person to sign in on this browser doesn't see the previous user's
+/// conversation before their own history (if any) loads.
+fn clear_message_history() {
+    if let Some(storage) = window().and_then(|w| w.local_storage().ok()).flatten() {
+        let _ = storage.remove_item(MESSAGE_HISTORY_STORAGE_KEY);
+    }
+}
+
+/// Briefly swaps the document title to `text`, restoring the original after
+/// a few seconds — enough to catch the eye of someone mentioned in a
+/// background tab.
+fn flash_title(text: &str) {
+    let Some(document) = window().and_then(|w| w.document()) else {
+        return;
+    };
+    let original = document.title();
+    document.set_title(text);
+    gloo_timers::callback::Timeout::new(3_000, move || {
+        document.set_title(&original);
+    })
+    .forget();
+}
+
+/// Replaces the text of the message with `id` with a tombstone placeholder
+/// rather than removing it, so it keeps its place (and any future thread or
+/// reply references) in `messages`. Returns whether a matching message was
+/// found — deleting an id that isn't there is a no-op, not an error.
+const DELETED_PLACEHOLDER: &str = "This message was deleted";
+
+fn delete_message(messages: &mut [MessageData], id: &str) -> bool {
+    match messages.iter_mut().find(|m| m.id.as_deref() == Some(id)) {
+        Some(m) => {
+            m.message = DELETED_PLACEHOLDER.to_string();
+            m.deleted = true;
+            m.image_url = None;
+            true
+        }
+        None => false,
+    }
+}
+
+/// How many messages `Chat::messages` holds by default before
+/// `push_bounded_messages` starts draining from the front — a long-running
+/// session would otherwise grow it forever, which costs both memory and
+/// render time. `Chat::message_cap` exists as a separate field (rather than
+/// just this constant) so it's easy to change per-instance later, e.g. from
+/// a settings panel.
+const DEFAULT_MESSAGE_CAP: usize = 500;
+
+/// Pushes `message` onto `messages`, then drains from the front until
+/// `messages.len() <= cap` — keeping the newest `cap` messages. Returns
+/// whatever got drained off the front so the caller can evict the same ids
+/// from `Chat::seen_message_ids` — otherwise that set would grow forever
+/// right along with the unbounded history this caps, and an id evicted here
+/// but still marked "seen" would wrongly suppress a later, legitimate
+/// redelivery (a reconnect replay, a `History` page) of a message nothing
+/// on screen still shows. Pulled out of `Chat::update` so the draining
+/// behavior can be unit tested without a full `Chat` instance.
+fn push_bounded_messages(messages: &mut Vec<MessageData>, cap: usize, message: MessageData) -> Vec<MessageData> {
+    messages.push(message);
+    if messages.len() > cap {
+        messages.drain(..messages.len() - cap).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// The fixed list of channels shown in the left rail; there's no
+/// create/delete flow yet, just a few named rooms everyone can switch
+/// between.
+const CHANNELS: [&str; 3] = ["general", "random", "help"];
+
+/// A short, locally-unique id for an outgoing message, good enough to
+/// match an `Edit` back up to it later — timestamp plus a random
+/// tiebreaker for messages sent in the same millisecond.
+fn generate_id() -> String {
+    format!("{}-{}", Date::now(), (js_sys::Math::random() * 1e9) as u64)
+}
+
+/// Formats a Unix-millis timestamp as a local `HH:MM` label for chat bubbles.
+fn format_timestamp(millis: f64) -> String {
+    let date = Date::new(&wasm_bindgen::JsValue::from_f64(millis));
+    format!("{:02}:{:02}", date.get_hours(), date.get_minutes())
+}
+
+/// Wraps `bytes` in a `Blob` and returns a `blob:` object URL the `<img>`
+/// tag can point at directly, without ever base64-encoding it into the DOM.
+fn blob_url_for(bytes: &[u8]) -> Result<String, JsValue> {
+    let array = Uint8Array::from(bytes);
+    let parts = Array::new();
+    parts.push(&array);
+    let blob = Blob::new_with_u8_array_sequence(&parts)?;
+    Url::create_object_url_with_blob(&blob)
+}
+
+/// How long the highlight ring [`scroll_to_message`] adds stays on the
+/// jumped-to bubble before it's removed again.
+const SCROLL_HIGHLIGHT_MS: u32 = 1_500;
+
+/// Scrolls the message bubble with dom id `msg-{id}` into view and briefly
+/// rings it, used when clicking a quoted reply preview to jump to the
+/// original message — a plain scroll alone leaves it ambiguous which of
+/// several bubbles on screen the quote was pointing at.
+fn scroll_to_message(id: &str) {
+    let Some(element) = window().and_then(|w| w.document()).and_then(|d| d.get_element_by_id(&format!("msg-{}", id))) else {
+        return;
+    };
+    element.scroll_into_view();
+    let class_list = element.class_list();
+    if class_list.add_1("ring-2").is_ok() && class_list.add_1("ring-yellow-400").is_ok() {
+        gloo_timers::callback::Timeout::new(SCROLL_HIGHLIGHT_MS, move || {
+            let _ = class_list.remove_2("ring-2", "ring-yellow-400");
+        })
+        .forget();
+    }
 }
 
 #[derive(Clone)]
 struct UserProfile {
     name: String,
     avatar: String,
+    status: UserStatus,
+    /// Tailwind text color class for `name`, stable across reloads since
+    /// it's derived purely from the name's bytes — see [`name_color`].
+    color: &'static str,
+}
+
+/// Presence status carried on each `MsgTypes::Users` roster entry.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UserStatus {
+    Online,
+    Away,
+    Offline,
+}
+
+/// How long `document` can go without a pointer or keyboard event before we
+/// report ourselves `Away` — activity resets the clock and reports `Online`
+/// again; see [`Msg::UserActivity`] and [`Msg::PresenceTick`].
+const IDLE_AWAY_MS: f64 = 5.0 * 60_000.0;
+
+/// How often [`Msg::PresenceTick`] fires to check our own idle state and
+/// prune stale `Offline` roster entries. Coarser than `TYPING_EXPIRY_MS`'s
+/// 1s prune cadence since presence changes on the scale of minutes, not
+/// keystrokes.
+const PRESENCE_TICK_MS: u32 = 10_000;
+
+/// How long a user who dropped out of the last `MsgTypes::Users` roster
+/// stays in `Chat::users` — greyed out as `Offline` — before being removed
+/// for good. Without this, a disconnect would make them (and their avatar
+/// next to their old messages) vanish from the sidebar the instant the next
+/// roster resync arrived.
+const OFFLINE_RETENTION_MS: f64 = 5.0 * 60_000.0;
+
+impl UserStatus {
+    fn dot_color(&self) -> &'static str {
+        match self {
+            UserStatus::Online => "bg-green-500",
+            UserStatus::Away => "bg-yellow-400",
+            UserStatus::Offline => "bg-gray-400",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            UserStatus::Online => "Online",
+            UserStatus::Away => "Away",
+            UserStatus::Offline => "Offline",
+        }
+    }
+}
+
+/// The structured `MsgTypes::Users` roster entry. Older servers send a
+/// plain username string instead; see [`parse_roster_entry`].
+#[derive(Deserialize)]
+struct RosterEntry {
+    name: String,
+    status: UserStatus,
+}
+
+/// Parses one `data_array` entry from `MsgTypes::Users`, accepting either
+/// the structured `{"name":...,"status":...}` shape or a bare username
+/// string from a server that predates presence status — treated as
+/// `Online` since the old protocol only ever listed users who were.
+fn parse_roster_entry(raw: &str, avatar_provider: &dyn AvatarProvider) -> UserProfile {
+    let (name, status) = match serde_json::from_str::<RosterEntry>(raw) {
+        Ok(entry) => (entry.name, entry.status),
+        Err(_) => (raw.to_string(), UserStatus::Online),
+    };
+    let avatar = avatar_provider.url_for(&name);
+    let color = name_color(&name);
+    UserProfile { name, avatar, status, color }
+}
+
+/// Colors `name_color` hashes a username into — muted enough to sit next to
+/// the existing message-bubble palette without clashing, but distinct
+/// enough at a glance to tell who's talking apart.
+const NAME_COLOR_PALETTE: [&str; 8] = [
+    "text-red-600",
+    "text-orange-600",
+    "text-amber-600",
+    "text-green-600",
+    "text-teal-600",
+    "text-blue-600",
+    "text-indigo-600",
+    "text-purple-600",
+];
+
+/// Hashes `name` with FNV-1a, reduced mod `len` — shared by [`name_color`]
+/// and the initials-avatar fallback's [`AVATAR_FALLBACK_PALETTE`] lookup so
+/// both land on the same name consistently, pure and independent of any
+/// stored state (so it still works for a message's `from` on a
+/// since-departed user, never in `Chat::users`).
+fn name_palette_index(name: &str, len: usize) -> usize {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in name.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash as usize % len
+}
+
+/// Maps `name` into [`NAME_COLOR_PALETTE`] — see [`name_palette_index`].
+fn name_color(name: &str) -> &'static str {
+    NAME_COLOR_PALETTE[name_palette_index(name, NAME_COLOR_PALETTE.len())]
+}
+
+/// Percent-encodes `value` for safe use as a URL query parameter — just
+/// enough (alphanumerics and `-_.~` pass through, everything else becomes
+/// `%XX`) to keep a username with spaces or punctuation from breaking
+/// [`avatar_url`]'s query string, without pulling in a dedicated crate for
+/// the one call site that needs it.
+fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Avatar URL for `name` on DiceBear's current v7 API — the v2
+/// `avatars.dicebear.com/api/...` endpoint this used to hit has been
+/// retired. `name` is percent-encoded into the `seed` query parameter so a
+/// username with spaces or punctuation still produces a valid URL.
+fn avatar_url(name: &str) -> String {
+    format!(
+        "https://api.dicebear.com/7.x/adventurer-neutral/svg?seed={}",
+        percent_encode(name)
+    )
+}
+
+/// Hex fill colors for the initials-avatar fallback, in the same order as
+/// [`NAME_COLOR_PALETTE`] so a name lands on a consistent color between the
+/// two (not the identical shade — this one needs to be legible as a solid
+/// circle fill rather than Tailwind text-on-white).
+const AVATAR_FALLBACK_PALETTE: [&str; 8] = [
+    "#dc2626", "#ea580c", "#d97706", "#16a34a", "#0d9488", "#2563eb", "#4f46e5", "#9333ea",
+];
+
+/// Up to two initials for `name`'s offline avatar fallback: the first
+/// letter of each of its first two whitespace-separated words, or the
+/// first two characters of a single-word name.
+fn initials(name: &str) -> String {
+    let mut words = name.split_whitespace();
+    match (words.next(), words.next()) {
+        (Some(first), Some(second)) => [first, second]
+            .iter()
+            .filter_map(|w| w.chars().next())
+            .map(|c| c.to_ascii_uppercase())
+            .collect(),
+        (Some(first), None) => first.chars().take(2).map(|c| c.to_ascii_uppercase()).collect(),
+        (None, _) => String::new(),
+    }
+}
+
+/// A self-contained `data:` SVG avatar for `name` — initials in a colored
+/// circle, picked the same way [`name_color`] picks a name's chip color —
+/// so a broken `avatar_url`/Gravatar fetch always has something to fall
+/// back to that never itself needs a network round trip to render.
+fn initials_avatar_url(name: &str) -> String {
+    let color = AVATAR_FALLBACK_PALETTE[name_palette_index(name, AVATAR_FALLBACK_PALETTE.len())];
+    let svg = format!(
+        r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 64 64"><circle cx="32" cy="32" r="32" fill="{}"/><text x="32" y="41" font-family="sans-serif" font-size="24" fill="white" text-anchor="middle">{}</text></svg>"#,
+        color,
+        initials(name)
+    );
+    format!("data:image/svg+xml,{}", percent_encode(&svg))
+}
+
+/// Swaps a broken avatar `<img>`'s `src` to [`initials_avatar_url`] for
+/// `name` — attached as `onerror` everywhere an avatar is rendered, so a
+/// down avatar provider or an invalid seed shows initials instead of a
+/// broken-image icon.
+fn avatar_fallback_onerror(name: String) -> Callback<Event> {
+    Callback::from(move |e: Event| {
+        if let Some(img) = e.target().and_then(|t| t.dyn_into::<web_sys::HtmlImageElement>().ok()) {
+            img.set_src(&initials_avatar_url(&name));
+        }
+    })
+}
+
+/// Source of per-user avatar URLs, so `Chat` can point at a different
+/// avatar service without editing `parse_roster_entry`/`avatar_for`
+/// themselves — just the provider it's constructed with.
+trait AvatarProvider {
+    fn url_for(&self, name: &str) -> String;
+}
+
+/// The default provider — DiceBear's v7 adventurer-neutral avatars, seeded
+/// on `name`. Preserves this client's original avatar look for anyone not
+/// explicitly configured otherwise.
+struct DiceBearProvider;
+
+impl AvatarProvider for DiceBearProvider {
+    fn url_for(&self, name: &str) -> String {
+        avatar_url(name)
+    }
+}
+
+/// Looks a name up on Gravatar, hashing it the same way Gravatar hashes an
+/// email address. This client only ever has a username to go on, not a
+/// verified email, so in practice this resolves to whatever (if anything)
+/// is registered under that exact string — anyone else gets Gravatar's own
+/// "identicon" fallback via `d=identicon`.
+struct GravatarProvider;
+
+impl AvatarProvider for GravatarProvider {
+    fn url_for(&self, name: &str) -> String {
+        let digest = md5::compute(name.trim().to_lowercase());
+        format!("https://www.gravatar.com/avatar/{:x}?d=identicon", digest)
+    }
+}
+
+/// Picks the avatar provider `Chat::create` should construct with, from a
+/// `?avatar=` query parameter the same way `config::resolve_ws_url` reads
+/// `?ws=` — `"gravatar"` selects [`GravatarProvider`]; anything else,
+/// including no parameter at all, keeps the [`DiceBearProvider`] this
+/// client has always used.
+fn select_avatar_provider() -> Box<dyn AvatarProvider> {
+    let requested = window()
+        .and_then(|w| w.location().search().ok())
+        .and_then(|search| web_sys::UrlSearchParams::new_with_str(&search).ok())
+        .and_then(|params| params.get("avatar"));
+    match requested.as_deref() {
+        Some("gravatar") => Box::new(GravatarProvider),
+        _ => Box::new(DiceBearProvider),
+    }
+}
+
+/// How close to the bottom (in pixels) the user has to be scrolled for a
+/// new message to auto-scroll the view; further away and we assume
+/// they're reading back through history and leave the scroll alone.
+const AUTO_SCROLL_THRESHOLD_PX: i32 = 64;
+
+/// How close to the top (in pixels) the messages pane has to be scrolled to
+/// trigger loading an older page of history — mirrors
+/// `AUTO_SCROLL_THRESHOLD_PX` for the opposite edge.
+const LOAD_MORE_THRESHOLD_PX: i32 = 150;
+
+/// How long the red "New" divider stays visible after the reader scrolls
+/// (or is scrolled) back to the bottom, before [`Msg::PruneTypingIndicators`]
+/// clears it.
+const UNREAD_DIVIDER_LINGER_MS: f64 = 3_000.0;
+
+/// Largest image the attach button will send, in bytes — past this the
+/// base64 payload would roughly double it again on the wire, so anything
+/// bigger gets rejected client-side with a notice instead of being sent.
+const MAX_IMAGE_BYTES: f64 = 500_000.0;
+
+/// Longest message body accepted client-side, in characters — past this the
+/// input is rejected with a notice instead of being sent.
+const MAX_MESSAGE_LENGTH: usize = 2_000;
+
+/// Minimum gap between outgoing typing notifications.
+const TYPING_THROTTLE_MS: f64 = 2_500.0;
+/// How long another user's typing indicator stays up after their last
+/// notification before we assume they stopped — the same role a per-user
+/// `gloo_timers::callback::Timeout`, reset on every new `Typing` event,
+/// would play. A single shared prune pass over `typing_users`' last-seen
+/// timestamps gets the identical behavior (a fresh event always pushes the
+/// deadline back, exactly like restarting a timer would) without juggling
+/// one `Timeout` per distinct typist or their cleanup on every expiry —
+/// and like any other field on `Chat`, `_typing_prune_interval` is dropped
+/// (canceling it) for free when the component is destroyed.
+const TYPING_EXPIRY_MS: f64 = 4_000.0;
+
+/// How long a toast from `push_notice` stays up before `PruneTypingIndicators`
+/// (which already ticks once a second for `typing_users`) clears it too,
+/// rather than spinning up a second interval just for notices.
+const NOTICE_EXPIRY_MS: f64 = 6_000.0;
+
+/// Renders the "is typing…" line above the input from the set of other
+/// users currently typing, collapsing anything past two names into "and N
+/// others" instead of listing everyone.
+fn typing_indicator_text(names: &[&str]) -> Option<String> {
+    match names {
+        [] => None,
+        [a] => Some(format!("{} is typing...", a)),
+        [a, b] => Some(format!("{} and {} are typing...", a, b)),
+        [a, rest @ ..] => Some(format!("{} and {} others are typing...", a, rest.len())),
+    }
+}
+
+/// Minimum gap between outgoing `MsgTypes::Read` reports, so dragging the
+/// scrollbar doesn't flood the server with one frame per pixel.
+const READ_RECEIPT_THROTTLE_MS: f64 = 2_000.0;
+
+/// Every other user (besides `exclude_user`) whose last reported read
+/// position in `read_up_to` is at or after `target_id`'s position in
+/// `messages` — i.e. everyone who's seen at least as far as that message.
+/// `target_id` or a reader's reported id not (yet) being in `messages`
+/// excludes that reader rather than guessing; see `read_up_to`'s doc
+/// comment on out-of-order delivery.
+fn readers_of<'a>(
+    messages: &[MessageData],
+    read_up_to: &'a std::collections::HashMap<String, String>,
+    target_id: &str,
+    exclude_user: &str,
+) -> Vec<&'a str> {
+    let index_of = |id: &str| messages.iter().position(|m| m.id.as_deref() == Some(id));
+    let Some(target_index) = index_of(target_id) else {
+        return Vec::new();
+    };
+    read_up_to
+        .iter()
+        .filter(|(user, _)| user.as_str() != exclude_user)
+        .filter(|(_, read_id)| index_of(read_id).is_some_and(|i| i >= target_index))
+        .map(|(user, _)| user.as_str())
+        .collect()
+}
+
+/// Consecutive messages from the same sender start a new group anyway once
+/// this much time has passed, so a "5 messages in a row" burst doesn't read
+/// as one continuous thought if it's actually spread across several minutes.
+const MESSAGE_GROUP_GAP_MS: f64 = 5.0 * 60_000.0;
+
+/// Whether `m` should show its own avatar and sender name rather than
+/// stacking under `prev` — true for the first message in `visible_messages`,
+/// a change of sender, or a gap of more than [`MESSAGE_GROUP_GAP_MS`].
+/// Missing timestamps never collapse into a group, since there's no way to
+/// tell whether the gap rule should apply.
+fn starts_new_group(prev: Option<&MessageData>, m: &MessageData) -> bool {
+    let Some(prev) = prev else {
+        return true;
+    };
+    if prev.from != m.from {
+        return true;
+    }
+    match (prev.timestamp, m.timestamp) {
+        (Some(prev_ts), Some(ts)) => ts - prev_ts > MESSAGE_GROUP_GAP_MS,
+        _ => true,
+    }
+}
+
+/// Formats the divider shown whenever a message falls on a different
+/// calendar day than the one before it — "Today" / "Yesterday" / the
+/// browser locale's short date for anything older, so skimming a long
+/// history doesn't need to parse full timestamps to tell days apart.
+fn day_divider_label(ts: f64) -> String {
+    let date = Date::new(&JsValue::from_f64(ts));
+    let now = Date::new_0();
+    let same_day = |a: &Date, b: &Date| {
+        a.get_full_year() == b.get_full_year() && a.get_month() == b.get_month() && a.get_date() == b.get_date()
+    };
+    if same_day(&date, &now) {
+        return "Today".to_string();
+    }
+    let yesterday = Date::new(&JsValue::from_f64(now.get_time() - 86_400_000.0));
+    if same_day(&date, &yesterday) {
+        return "Yesterday".to_string();
+    }
+    date.to_locale_date_string("default", &JsValue::UNDEFINED).into()
+}
+
+/// The glyph (and its color class) shown next to the timestamp of your own
+/// messages, mirroring the familiar single/double-check convention.
+fn status_glyph(status: MessageStatus) -> (&'static str, &'static str) {
+    match status {
+        MessageStatus::Sending => ("🕐", "text-gray-400"),
+        MessageStatus::Sent => ("✓", "text-gray-400"),
+        MessageStatus::Delivered => ("✓✓", "text-gray-400"),
+        MessageStatus::Read => ("✓✓", "text-blue-500"),
+    }
+}
+
+/// Renders the "seen by…" label shown under the last own message everyone
+/// visible has read, mirroring `typing_indicator_text`'s name-collapsing.
+fn seen_by_text(mut names: Vec<&str>) -> Option<String> {
+    names.sort_unstable();
+    match names.as_slice() {
+        [] => None,
+        [a] => Some(format!("Seen by {}", a)),
+        [a, b] => Some(format!("Seen by {} and {}", a, b)),
+        [a, rest @ ..] => Some(format!("Seen by {} and {} others", a, rest.len())),
+    }
 }
 
 pub struct Chat {
-    users: Vec<UserProfile>,
+    /// Roster of the current room, keyed by username for O(1) avatar and
+    /// mention lookups instead of a linear scan per message. Refreshed from
+    /// every `MsgTypes::Users` frame, but not wholesale — a name missing
+    /// from a new frame is kept around as `Offline` (see `offline_since`)
+    /// rather than dropped immediately; see `user_order` for the sidebar's
+    /// display order.
+    users: std::collections::HashMap<String, UserProfile>,
+    /// Usernames known to the sidebar, in the order the server most
+    /// recently sent them — `users` alone wouldn't have a stable iteration
+    /// order to render it in. Names pending removal (see `offline_since`)
+    /// stay at the end of this list until they're pruned.
+    user_order: Vec<String>,
+    /// When each name in `offline_since` fell out of the last
+    /// `MsgTypes::Users` frame — [`Msg::PresenceTick`] removes it from
+    /// `users`/`user_order` once [`OFFLINE_RETENTION_MS`] has passed rather
+    /// than the moment they're first missing.
+    offline_since: std::collections::HashMap<String, f64>,
     chat_input: NodeRef,
+    /// Hidden `<input type="file">` behind the paperclip button; read via
+    /// `Msg::AttachImage` and reset to empty after every pick so the same
+    /// file can be selected again.
+    image_input: NodeRef,
+    /// The search box shown below the header while `search_open`; read on
+    /// every keystroke to update `search_query`.
+    search_input: NodeRef,
+    messages_container: NodeRef,
+    should_stick_to_bottom: bool,
+    /// How many messages have arrived in the room or DM currently on screen
+    /// since the reader scrolled away from the bottom — shown on the
+    /// "jump to latest" button (see `Msg::JumpToLatest`), cleared once
+    /// they scroll back down on their own or click it.
+    missed_while_scrolled: usize,
+    /// Id of the last message that was on screen when the reader scrolled
+    /// away from the bottom — the red "New" divider renders just after it.
+    /// `None` means nothing's missed, so no divider shows.
+    unread_divider_after: Option<String>,
+    /// `Date::now()` the divider should be cleared at, set once the reader
+    /// reaches the bottom again — [`UNREAD_DIVIDER_LINGER_MS`] after that
+    /// moment rather than the instant it happens, so it doesn't vanish out
+    /// from under them mid-read. Checked by the same interval that prunes
+    /// typing indicators and expired messages.
+    unread_divider_clear_at: Option<f64>,
     _producer: Box<dyn Bridge<EventBus>>,
-    wss: WebsocketService,
-    messages: Vec<MessageData>,
+    wss: Rc<WebsocketService>,
+    /// The shared-connection slot `wss` was pulled from — kept around so
+    /// `Msg::Logout` can invalidate it, not just close `wss` itself. See
+    /// `Msg::Logout` for why closing alone isn't enough.
+    wss_handle: WsHandle,
+    /// Public channel history, keyed by room name. Per-room rather than one
+    /// shared `Vec` so a burst of traffic in one room can't push another,
+    /// quieter room's entire history out of `message_cap` — each room gets
+    /// its own cap instead of all of them fighting over a single one.
+    messages: std::collections::HashMap<String, Vec<MessageData>>,
+    /// Join/leave notices synthesized from `MsgTypes::Join`/`MsgTypes::Leave`,
+    /// keyed by room the same way `messages` is — interleaved with that
+    /// room's history by timestamp at render time rather than stored inline,
+    /// since they're never edited, reacted to, or persisted across a reload.
+    system_events: std::collections::HashMap<String, Vec<SystemEvent>>,
+    /// Rooms with a `MsgTypes::History` request outstanding — shows a
+    /// loading placeholder at the top of the pane until the reply (or a
+    /// room switch away) clears it.
+    history_loading: std::collections::HashSet<String>,
+    /// Rooms that have already had their backlog fetched, so switching back
+    /// to one (or reconnecting into it) doesn't ask again.
+    history_loaded: std::collections::HashSet<String>,
+    /// Rooms for which the server has said there's nothing older than what
+    /// we already have — scrolling to the top stops asking for another page
+    /// and shows a "beginning of conversation" marker instead of a spinner.
+    history_exhausted: std::collections::HashSet<String>,
+    /// Set right before prepending an older page of history, to the
+    /// container's `(scroll_height, scroll_top)` at that moment — `rendered`
+    /// uses it to shift `scroll_top` by however much the prepend grew the
+    /// content, so the messages the reader was already looking at don't
+    /// jump around under them.
+    pending_scroll_restore: Option<(f64, f64)>,
+    /// Ids already folded into `messages` (or into a `dm_messages` thread),
+    /// mirrored alongside it so a redelivered `MsgTypes::Message` — the
+    /// server retries a send it never got an ack for, or the `EventBus`
+    /// replay buffer hands us one we already have — can be dropped in O(1)
+    /// instead of scanning the whole history for a match. An id evicted from
+    /// `messages` by [`push_bounded_messages`] is evicted from here too, so
+    /// this stays no bigger than the history it's deduping against, and a
+    /// message that's fallen out of view can be legitimately redelivered
+    /// later instead of being dropped forever.
+    seen_message_ids: std::collections::HashSet<String>,
+    /// Ids a `MsgTypes::Delete` named before the matching `Message` ever
+    /// showed up here — the server can broadcast them out of order after a
+    /// reconnect. Checked (and cleared) every time a new message is folded
+    /// in, so a delete that arrived early still lands as a tombstone instead
+    /// of being silently dropped.
+    pending_deletes: std::collections::HashSet<String>,
+    /// Reactions for a message id not yet in `messages` — (id, payload,
+    /// time queued) — replayed once that id shows up, or dropped after
+    /// [`REACTION_BUFFER_MS`]. See [`apply_pending_reactions`].
+    pending_reactions: Vec<(String, ReactionPayload, f64)>,
+    /// Upper bound on `messages.len()`, enforced by `push_bounded_messages`
+    /// — see [`DEFAULT_MESSAGE_CAP`].
+    message_cap: usize,
+    /// The room's pinned messages, in the order they were pinned — what the
+    /// "📌 Pinned" strip actually renders from, rather than filtering
+    /// `messages` for `pinned: true` every render, so an entry still shows
+    /// even if its message has since scrolled out of loaded history.
+    pinned: Vec<PinnedMessage>,
+    /// Whether the "📌 Pinned" strip is expanded.
+    pinned_strip_open: bool,
+    /// Messages with an optimistic pin/unpin outstanding, keyed by id, with
+    /// the `pinned` value to restore if the server comes back with a
+    /// `MsgTypes::Error` naming this id — mirrors how `find_message_mut`
+    /// reverts a failed send, just for this second kind of optimistic
+    /// action.
+    pending_pin_reverts: std::collections::HashMap<String, bool>,
+    connection_state: ConnectionState,
+    reconnect_attempt: u32,
+    /// Whether the server has sent back a `Register` ack for our username.
+    /// The socket can be `ConnectionState::Open` well before this flips —
+    /// the header shows a "Registering..." spinner and the send button
+    /// stays disabled for that gap. Reset whenever the connection drops,
+    /// since the server will need to re-register us on the reconnect.
+    registered: bool,
+    username: String,
+    config_error: Option<String>,
+    typing_users: std::collections::HashMap<String, f64>,
+    last_typing_sent_at: f64,
+    _typing_prune_interval: gloo_timers::callback::Interval,
+    /// Last message id each other user is known to have read, keyed by
+    /// username, from incoming `MsgTypes::Read` frames. A frame naming a
+    /// message we haven't received yet is stored as-is — the wire gives no
+    /// ordering guarantee between a `Message` and a `Read` for it — and
+    /// simply has no effect on `seen_by_text` until that message shows up
+    /// in `messages`.
+    read_up_to: std::collections::HashMap<String, String>,
+    /// Id of the last message we reported as read, so `Msg::MessagesScrolled`
+    /// can skip re-sending a `Read` frame when nothing's changed.
+    last_read_id_sent: Option<String>,
+    last_read_sent_at: f64,
+    /// Id of the message currently being edited, if any; the chat input is
+    /// pre-filled with its text and the next submit sends an `Edit` instead
+    /// of a new `Message`.
+    editing_id: Option<String>,
+    /// Character count of the chat input, tracked separately from the
+    /// throttled `MsgTypes::Typing` broadcast so the counter under the input
+    /// updates on every keystroke even while that broadcast is held back.
+    compose_len: usize,
+    /// Dismissible notices shown above the message pane — send failures,
+    /// serialization errors, and anything else that would otherwise only
+    /// have gone to `log::warn!`.
+    notices: Vec<Notice>,
+    /// Id of the message the next submit should reply to, if the user
+    /// clicked "Reply"; shown as a cancellable chip above the input.
+    replying_to: Option<String>,
+    /// Seconds the next submit should set as the sent message's
+    /// `expires_in`, if the user armed the ⏱ toggle; a `/tmp <seconds>`
+    /// command bypasses this and sets its own TTL directly. Consumed (reset
+    /// to `None`) by the next send, the same way `replying_to` is.
+    ephemeral_ttl: Option<u64>,
+    /// Channel currently shown in the message pane; looks itself up in
+    /// `self.messages` in `view`.
+    current_room: String,
+    /// Messages received for a room other than `current_room` since it was
+    /// last switched to, shown as a badge in the channel list.
+    unread_counts: std::collections::HashMap<String, u32>,
+    /// Which peer's DM thread the message pane is showing, or `None` for
+    /// the public `current_room` channel — the two views are mutually
+    /// exclusive tabs.
+    active_dm: Option<String>,
+    /// DM history with each peer who has an open tab, keyed by username.
+    dm_messages: std::collections::HashMap<String, Vec<MessageData>>,
+    /// Usernames with an open DM tab, in the order the tabs are shown.
+    open_dm_tabs: Vec<String>,
+    /// DMs received from a peer while their tab wasn't the active view,
+    /// shown as a badge on the tab.
+    dm_unread: std::collections::HashMap<String, u32>,
+    /// Thread replies keyed by their root message's id, kept separate from
+    /// `messages` so a reply never renders twice — once inline, once in the
+    /// thread panel. Updated from an incoming `Message` carrying a
+    /// `thread_root` whether or not `open_thread` points at that root, so
+    /// the "N replies" count under the root stays accurate while the panel
+    /// is closed.
+    thread_replies: std::collections::HashMap<String, Vec<MessageData>>,
+    /// Id of the root message whose thread panel is currently open, if any.
+    open_thread: Option<String>,
+    /// Input box inside the open thread panel — separate from `chat_input`
+    /// since both can be on screen at once.
+    thread_input: NodeRef,
+    /// Most recent snapshot from `WebsocketService::metrics`, sent
+    /// periodically over the `EventBus` as `MsgTypes::Metrics`. `None`
+    /// until the first one arrives, so the header widget can hide itself.
+    metrics: Option<ConnectionMetrics>,
+    /// Set once a `MsgTypes::UpgradeRequired` frame shows this client's
+    /// `PROTOCOL_VERSION` is too old — blocks the whole chat view with an
+    /// overlay telling the user to refresh, since trusting any further
+    /// frame from a server running an incompatible wire format risks
+    /// misparsing it instead of just failing loudly.
+    upgrade_required: Option<u32>,
+    /// Messages that have arrived since the tab was last focused; shown as
+    /// a "(N)" prefix on the document title and reset once it regains
+    /// focus. See [`document_hidden`] and [`set_unread_title`].
+    unread: usize,
+    /// `@mentions` of `self.username` since the tab was last focused —
+    /// tracked separately from `unread` so the header can call out "you
+    /// were mentioned" rather than just "something happened", reset the
+    /// same way on `Msg::VisibilityChanged`.
+    mentions_count: usize,
+    /// Keeps the `visibilitychange` listener registered in `create` alive
+    /// for as long as this component exists; detached again in `destroy`.
+    _visibility_listener: Closure<dyn Fn()>,
+    /// Keeps the `pointermove`/`keydown` listeners registered in `create`
+    /// alive; detached again in `destroy`. Both events share this one
+    /// closure — `Msg::UserActivity` doesn't care which fired.
+    _activity_listener: Closure<dyn Fn()>,
+    /// `Date::now()` of the last `Msg::UserActivity`, checked by
+    /// `Msg::PresenceTick` against [`IDLE_AWAY_MS`].
+    last_activity_at: f64,
+    /// Our own last-reported presence, so `Msg::PresenceTick` only sends a
+    /// `MsgTypes::Presence` frame on an actual transition rather than
+    /// repeating `Away` every tick.
+    self_presence: UserStatus,
+    /// Drives `Msg::PresenceTick`; dropped (canceling it) when the
+    /// component is destroyed, same as `_typing_prune_interval`.
+    _presence_tick_interval: gloo_timers::callback::Interval,
+    /// Held live (not copied like `username`) so `notifications_enabled`
+    /// reflects the permission grant even if it resolves after this
+    /// component was created.
+    user: User,
+    /// Whether an incoming message plays a sound; toggled from the header
+    /// and persisted to `localStorage` under [`SOUND_ENABLED_STORAGE_KEY`].
+    sound_enabled: bool,
+    /// `Date::now()` of the last time a message sound was played, so a
+    /// burst of messages arriving together only plays one — see
+    /// [`play_message_sound`].
+    last_sound_at: f64,
+    /// Which color scheme `view` renders; toggled from the header and
+    /// persisted to `localStorage` under [`THEME_STORAGE_KEY`].
+    theme: Theme,
+    /// Whether the emoji picker below the attach button is open.
+    emoji_picker_open: bool,
+    /// Index into `EMOJI_GRID` highlighted while navigating it with arrow
+    /// keys; reset to `0` whenever the picker opens.
+    emoji_picker_focus: usize,
+    /// Emojis picked most recently, most-recent-first, shown as a quick-pick
+    /// row above the full grid; persisted to `localStorage` under
+    /// [`RECENT_EMOJIS_STORAGE_KEY`].
+    recent_emojis: Vec<String>,
+    /// Whether the search box below the header is open.
+    search_open: bool,
+    /// Current search box contents; empty means "no active search" and the
+    /// message pane renders normally.
+    search_query: String,
+    /// Index into the current match list that `Enter` last jumped to;
+    /// `None` before the first jump or once the query stops matching
+    /// anything.
+    search_match_index: Option<usize>,
+    /// Source of `users[..].avatar` and `avatar_for`'s fallback URLs,
+    /// resolved once in `create` by [`select_avatar_provider`] — boxed so
+    /// swapping it for a [`GravatarProvider`] doesn't touch either call
+    /// site.
+    avatar_provider: Box<dyn AvatarProvider>,
 }
-impl Component for Chat {
-    type Message = Msg;
-    type Properties = ();
 
-    fn create(ctx: &Context<Self>) -> Self {
-        let (user, _) = ctx
-            .link()
-            .context::<User>(Callback::noop())
-            .expect("context to be set");
-        let wss = WebsocketService::new();
-        let username = user.username.borrow().clone();
+struct Notice {
+    id: String,
+    text: String,
+    /// `Date::now()` this notice was pushed, so `PruneTypingIndicators` can
+    /// clear it once it's older than [`NOTICE_EXPIRY_MS`].
+    created_at: f64,
+}
 
-        let message = WebSocketMessage {
-            message_type: MsgTypes::Register,
-            data: Some(username.to_string()),
-            data_array: None,
-        };
+/// Builds a `Register` message and hands it to `wss` as the payload to
+/// remember and automatically resend on every future connection — see
+/// `WebsocketService::register`. Called on first connect and again
+/// whenever `room` changes, so a later reconnect lands back in whichever
+/// channel was actually active instead of the one from startup.
+fn send_register(wss: &WebsocketService, username: &str, room: &str) {
+    let message = WebSocketMessage {
+        data: Some(username.to_string()),
+        room: room.to_string(),
+        protocol_version: Some(PROTOCOL_VERSION),
+        ..WebSocketMessage::new(MsgTypes::Register)
+    };
+    wss.register(message);
+}
 
-        if let Ok(_) = wss
-            .tx
-            .clone()
-            .try_send(serde_json::to_string(&message).unwrap())
-        {
-            log::debug!("message sent successfully");
-        }
+/// How many of a room's most recent messages a `MsgTypes::History` request
+/// asks for — generous enough to fill the pane on a typical screen without
+/// asking the server to ship its entire backlog.
+const HISTORY_REQUEST_LIMIT: u32 = 50;
 
-        Self {
-            users: vec![],
-            messages: vec![],
-            chat_input: NodeRef::default(),
-            wss,
-            _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
-        }
+/// Builds and sends a `History` request for `room`'s backlog — called once
+/// on first joining a room (see `Chat::create` and `Msg::SwitchRoom`) with
+/// `before: None` for the newest page, and again for each older page as the
+/// reader scrolls up (see `Msg::MessagesScrolled`), with `before` set to the
+/// oldest message id loaded so far so the server knows where to resume.
+fn send_history_request(wss: &WebsocketService, room: &str, before: Option<String>) {
+    let message = WebSocketMessage {
+        data: Some(HISTORY_REQUEST_LIMIT.to_string()),
+        before,
+        room: room.to_string(),
+        ..WebSocketMessage::new(MsgTypes::History)
+    };
+    wss.send(message);
+}
+impl Chat {
+    /// Persists `self.messages` to `localStorage` — called after every
+    /// mutation of it so a reload never loses more than what happened
+    /// between the last change and now.
+    fn persist_messages(&self) {
+        save_message_history(&self.messages);
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
-        match msg {
-            Msg::HandleMsg(s) => {
-                let msg: WebSocketMessage = serde_json::from_str(&s).unwrap();
-                match msg.message_type {
+    /// Reports our own presence as `status`, both to the server and to
+    /// `self_presence` so the next idle check knows whether anything's
+    /// actually changed before sending another frame.
+    fn send_presence(&mut self, status: UserStatus) {
+        self.self_presence = status;
+        let payload = PresencePayload {
+            user: self.username.clone(),
+            status,
+        };
+        let data = match serde_json::to_string(&payload) {
+            Ok(data) => data,
+            Err(e) => {
+                log::warn!("failed to serialize outgoing presence: {}", e);
+                return;
+            }
+        };
+        let message = WebSocketMessage {
+            data: Some(data),
+            room: self.current_room.clone(),
+            ..WebSocketMessage::new(MsgTypes::Presence)
+        };
+        self.wss.send(message);
+    }
+
+    /// Queues a dismissible notice for the user, in place of a
+    /// `log::warn!` they'd never see.
+    fn push_notice(&mut self, text: impl Into<String>) {
+        self.notices.push(Notice {
+            id: generate_id(),
+            text: text.into(),
+            created_at: Date::now(),
+        });
+    }
+
+    /// `self.current_room`'s history, or `&[]` if nothing has arrived for it
+    /// yet — pulled out so callers don't need to know `messages` is keyed by
+    /// room rather than a single flat `Vec`.
+    fn current_room_messages(&self) -> &[MessageData] {
+        self.messages.get(&self.current_room).map(Vec::as_slice).unwrap_or(&[])
+    }
+
+    /// `room`'s `Vec`, creating an empty one on first message if this is the
+    /// first time anyone's posted there.
+    fn room_messages_mut(&mut self, room: &str) -> &mut Vec<MessageData> {
+        self.messages.entry(room.to_string()).or_default()
+    }
+
+    /// The messages the message pane should currently show: `current_room`'s
+    /// history, or a DM thread if `active_dm` is open — the two are mutually
+    /// exclusive tabs.
+    fn visible_messages(&self) -> Vec<&MessageData> {
+        match &self.active_dm {
+            Some(peer) => self.dm_messages.get(peer).map(|thread| thread.iter().collect()).unwrap_or_default(),
+            None => self.current_room_messages().iter().collect(),
+        }
+    }
+
+    /// Whether `m` matches the active search box, case-insensitively, on
+    /// either `from` or `message`. Always `false` while `search_query` is
+    /// empty, so an empty box never highlights everything. A whisper `m`
+    /// isn't addressed to us only ever matches if we're also the sender —
+    /// search shouldn't surface the content of a private aside we weren't
+    /// part of just because it's sitting in the same room's history.
+    fn is_search_match(&self, m: &MessageData) -> bool {
+        if self.search_query.is_empty() {
+            return false;
+        }
+        if !m.recipients.is_empty() && m.from != self.username && !m.recipients.iter().any(|r| r == &self.username) {
+            return false;
+        }
+        let query = self.search_query.to_lowercase();
+        m.from.to_lowercase().contains(&query) || m.message.to_lowercase().contains(&query)
+    }
+
+    /// Ids of the currently visible messages matching the active search
+    /// box, in display order — only messages with an id can be jumped to
+    /// with `Msg::SearchNext`, which is every message except the synthetic
+    /// ones representing a received image.
+    fn search_match_ids(&self) -> Vec<String> {
+        self.visible_messages()
+            .iter()
+            .filter(|m| self.is_search_match(m))
+            .filter_map(|m| m.id.clone())
+            .collect()
+    }
+
+    /// Read-only counterpart to [`find_message_mut`](Self::find_message_mut),
+    /// used to build a [`reply_snippet`](MessageData::reply_snippet) from
+    /// whichever message is being replied to.
+    fn find_message(&self, id: &str) -> Option<&MessageData> {
+        self.messages
+            .values()
+            .find_map(|room| room.iter().find(|m| m.id.as_deref() == Some(id)))
+            .or_else(|| self.dm_messages.values().find_map(|thread| thread.iter().find(|m| m.id.as_deref() == Some(id))))
+            .or_else(|| self.thread_replies.values().find_map(|thread| thread.iter().find(|m| m.id.as_deref() == Some(id))))
+    }
+
+    /// Finds the message with `id`, checking every room, then every open DM
+    /// thread, then every thread panel's replies — used to resolve a send's
+    /// outcome without the caller needing to know which one it was sent
+    /// into.
+    fn find_message_mut(&mut self, id: &str) -> Option<&mut MessageData> {
+        if let Some(m) = self.messages.values_mut().find_map(|room| room.iter_mut().find(|m| m.id.as_deref() == Some(id))) {
+            return Some(m);
+        }
+        if let Some(m) = self
+            .dm_messages
+            .values_mut()
+            .find_map(|thread| thread.iter_mut().find(|m| m.id.as_deref() == Some(id)))
+        {
+            return Some(m);
+        }
+        self.thread_replies
+            .values_mut()
+            .find_map(|thread| thread.iter_mut().find(|m| m.id.as_deref() == Some(id)))
+    }
+
+    /// Avatar URL for `name`, falling back to a generated default if the
+    /// sender isn't (or isn't yet) in `self.users` — e.g. they left right
+    /// after posting, or their `Users` update hasn't landed yet.
+    fn avatar_for(&self, name: &str) -> String {
+        self.users
+            .get(name)
+            .map(|u| u.avatar.clone())
+            .unwrap_or_else(|| self.avatar_provider.url_for(name))
+    }
+
+    /// Whether the messages container is already scrolled close enough to
+    /// the bottom that an incoming message should pull the view down with
+    /// it, rather than leaving a reader mid-scroll undisturbed.
+    fn is_scrolled_near_bottom(&self) -> bool {
+        match self.messages_container.cast::<HtmlElement>() {
+            Some(container) => {
+                let distance_from_bottom =
+                    container.scroll_height() - container.scroll_top() - container.client_height();
+                distance_from_bottom <= AUTO_SCROLL_THRESHOLD_PX
+            }
+            None => true,
+        }
+    }
+
+    /// Whether the messages container is scrolled close enough to the top
+    /// that it's time to ask for an older page of history.
+    fn is_scrolled_near_top(&self) -> bool {
+        match self.messages_container.cast::<HtmlElement>() {
+            Some(container) => container.scroll_top() <= LOAD_MORE_THRESHOLD_PX,
+            None => false,
+        }
+    }
+
+    /// Updates `should_stick_to_bottom` for a message that just arrived, and
+    /// bumps `missed_while_scrolled` if it landed in the room or DM the
+    /// reader is actually looking at while they're scrolled away from the
+    /// bottom — called once per arriving message, before it's pushed into
+    /// `messages`/`dm_messages` so the scroll check reflects the
+    /// pre-arrival DOM.
+    fn note_arrival(&mut self, in_current_view: bool) {
+        self.should_stick_to_bottom = self.is_scrolled_near_bottom();
+        if in_current_view && !self.should_stick_to_bottom {
+            if self.missed_while_scrolled == 0 {
+                // First message missed since the reader scrolled away —
+                // the divider goes right after whatever was last on screen.
+                self.unread_divider_after = self.visible_messages().last().and_then(|m| m.id.clone());
+            }
+            self.missed_while_scrolled += 1;
+        }
+    }
+
+    /// Clears `missed_while_scrolled` for a reader who's caught up with the
+    /// bottom of the pane, and starts the "New" divider's fade-out clock
+    /// rather than clearing it immediately — so it's still there to orient
+    /// them for a moment after they arrive.
+    fn mark_caught_up(&mut self) {
+        self.missed_while_scrolled = 0;
+        if self.unread_divider_after.is_some() && self.unread_divider_clear_at.is_none() {
+            self.unread_divider_clear_at = Some(Date::now() + UNREAD_DIVIDER_LINGER_MS);
+        }
+    }
+
+    /// Splits `text` on whitespace and interleaves highlighted spans for
+    /// any `@name` token matching a known user and any bare `http(s)://`
+    /// URL; everything else renders as plain text. Used as the leaf-text
+    /// renderer inside [`render_markdown_with`] so mentions and auto-linked
+    /// URLs still work inside Markdown-formatted messages.
+    fn mention_spans(&self, text: &str) -> Vec<Html> {
+        let mut parts: Vec<Html> = Vec::new();
+        for (i, word) in text.split(' ').enumerate() {
+            if i > 0 {
+                parts.push(html! { {" "} });
+            }
+            parts.push(self.render_word(word));
+        }
+        parts
+    }
+
+    /// Renders one whitespace-separated `word` from [`mention_spans`]: an
+    /// `@name` matching a known user (case-insensitively, rendered back
+    /// with whatever casing the roster has it under), a bare URL, or plain
+    /// text, in that order — either way trailing punctuation not part of
+    /// the token
+    /// (`@bob,`, `https://example.com.`) is split off and rendered
+    /// unstyled after it.
+    fn render_word(&self, word: &str) -> Html {
+        let mention = word.strip_prefix('@').and_then(|name| {
+            let trimmed = name.trim_end_matches(|c: char| !c.is_alphanumeric() && c != '_');
+            self.users
+                .keys()
+                .find(|known| known.eq_ignore_ascii_case(trimmed))
+                .map(|known| (known.clone(), name[trimmed.len()..].to_string()))
+        });
+        if let Some((name, suffix)) = mention {
+            return html! {
+                <>
+                    <span class="bg-yellow-200 text-blue-900 rounded px-1">{format!("@{}", name)}</span>
+                    {suffix}
+                </>
+            };
+        }
+        if let Some((url, suffix)) = linkify(word) {
+            return html! {
+                <>
+                    <a href={url.clone()} target="_blank" rel="noopener noreferrer" class="underline">
+                        {truncate_url_for_display(&url)}
+                    </a>
+                    {suffix}
+                </>
+            };
+        }
+        html! { {word.to_string()} }
+    }
+
+    /// Renders `text` as Markdown, highlighting `@mentions` the same way
+    /// [`mention_spans`](Self::mention_spans) does everywhere else.
+    fn render_message_body(&self, text: &str) -> Html {
+        render_markdown_with(text, |t| self.mention_spans(t))
+    }
+}
+
+/// A frame on [`render_markdown_with`]'s stack: an ordinary tag accumulates
+/// rendered `Html` children, but a `CodeBlock` accumulates its raw text
+/// instead, so its indentation and blank lines survive untouched by
+/// `render_text` (which is built for prose, not source) until
+/// [`highlight_code`] renders the whole block at once.
+enum MarkdownFrame {
+    Nodes(Vec<Html>),
+    Code(String),
+}
+
+/// Walks `text`'s Markdown events, rendering bold/italic/code/links and
+/// running `render_text` over every literal run in between — the hook
+/// [`Chat::render_message_body`] uses to keep `@mention` highlighting
+/// working inside Markdown-formatted messages. Raw HTML in the source (an
+/// `Event::Html`) is handed to `render_text` just like any other text run
+/// rather than interpreted, so a message can't inject a script tag.
+fn render_markdown_with(text: &str, mut render_text: impl FnMut(&str) -> Vec<Html>) -> Html {
+    use pulldown_cmark::{Event, Parser, Tag};
+
+    let mut stack: Vec<(Option<Tag>, MarkdownFrame)> = vec![(None, MarkdownFrame::Nodes(Vec::new()))];
+    for event in Parser::new(text) {
+        match event {
+            Event::Start(tag @ Tag::CodeBlock(_)) => stack.push((Some(tag), MarkdownFrame::Code(String::new()))),
+            Event::Start(tag) => stack.push((Some(tag), MarkdownFrame::Nodes(Vec::new()))),
+            Event::End(_) => {
+                let (tag, frame) = stack.pop().expect("Start/End events balance");
+                let node = wrap_markdown_tag(tag.expect("End always follows a Start"), frame);
+                push_node(&mut stack, node);
+            }
+            Event::Text(t) => match &mut stack.last_mut().expect("root frame is never popped").1 {
+                MarkdownFrame::Code(code) => code.push_str(t.as_ref()),
+                MarkdownFrame::Nodes(nodes) => nodes.extend(render_text(t.as_ref())),
+            },
+            Event::Code(t) => push_node(
+                &mut stack,
+                html! { <code class="bg-gray-500 bg-opacity-20 rounded px-1">{t.into_string()}</code> },
+            ),
+            Event::Html(raw) => match &mut stack.last_mut().expect("root frame is never popped").1 {
+                MarkdownFrame::Code(code) => code.push_str(raw.as_ref()),
+                MarkdownFrame::Nodes(nodes) => nodes.extend(render_text(raw.as_ref())),
+            },
+            Event::SoftBreak => match &mut stack.last_mut().expect("root frame is never popped").1 {
+                MarkdownFrame::Code(code) => code.push('\n'),
+                MarkdownFrame::Nodes(_) => push_node(&mut stack, html! { {" "} }),
+            },
+            Event::HardBreak => match &mut stack.last_mut().expect("root frame is never popped").1 {
+                MarkdownFrame::Code(code) => code.push('\n'),
+                MarkdownFrame::Nodes(_) => push_node(&mut stack, html! { <br/> }),
+            },
+            Event::Rule | Event::FootnoteReference(_) | Event::TaskListMarker(_) => {}
+        }
+    }
+    let (_, root) = stack.pop().expect("root frame is always present");
+    let MarkdownFrame::Nodes(root_children) = root else {
+        unreachable!("the root frame is never a CodeBlock");
+    };
+    html! { <>{ for root_children }</> }
+}
+
+/// Pushes `node` onto the `Nodes` frame at the top of `stack` — every frame
+/// but a `CodeBlock`'s, which never contains another tag's `Start`/`End`
+/// per the CommonMark grammar.
+fn push_node(stack: &mut [(Option<pulldown_cmark::Tag>, MarkdownFrame)], node: Html) {
+    match &mut stack.last_mut().expect("root frame is never popped").1 {
+        MarkdownFrame::Nodes(nodes) => nodes.push(node),
+        MarkdownFrame::Code(_) => unreachable!("a CodeBlock frame never receives a nested node"),
+    }
+}
+
+/// Renders `text` as Markdown with no special handling of its literal text
+/// runs beyond what Markdown itself defines — see [`render_markdown_with`]
+/// for the version that lets a caller hook those runs.
+fn render_markdown(text: &str) -> Html {
+    render_markdown_with(text, |t| vec![html! { {t.to_string()} }])
+}
+
+fn wrap_markdown_tag(tag: pulldown_cmark::Tag, frame: MarkdownFrame) -> Html {
+    use pulldown_cmark::{CodeBlockKind, Tag};
+
+    match (tag, frame) {
+        (Tag::Emphasis, MarkdownFrame::Nodes(children)) => html! { <em>{ for children }</em> },
+        (Tag::Strong, MarkdownFrame::Nodes(children)) => html! { <strong>{ for children }</strong> },
+        (Tag::Strikethrough, MarkdownFrame::Nodes(children)) => html! { <s>{ for children }</s> },
+        (Tag::CodeBlock(kind), MarkdownFrame::Code(code)) => {
+            let lang = match kind {
+                CodeBlockKind::Fenced(lang) => lang.to_string(),
+                CodeBlockKind::Indented => String::new(),
+            };
+            render_code_block(&lang, &code)
+        }
+        (Tag::Link(_, dest, _), MarkdownFrame::Nodes(children)) => match safe_href(&dest) {
+            Some(href) => html! {
+                <a href={href} target="_blank" rel="noopener noreferrer" class="underline">{ for children }</a>
+            },
+            None => html! { <>{ for children }</> },
+        },
+        (_, MarkdownFrame::Nodes(children)) => html! { <>{ for children }</> },
+        (_, MarkdownFrame::Code(code)) => html! { {code} },
+    }
+}
+
+/// A fenced or indented code block: syntax-highlighted per [`highlight_code`]
+/// and given a "Copy" button, since pasted-in code is common enough in chat
+/// to be worth one click instead of a manual select-and-copy.
+fn render_code_block(lang: &str, code: &str) -> Html {
+    let code_to_copy = code.to_string();
+    let onclick = Callback::from(move |_: MouseEvent| copy_to_clipboard(&code_to_copy));
+    html! {
+        <div class="relative group">
+            <pre class="bg-gray-500 bg-opacity-20 rounded p-2 text-xs overflow-x-auto whitespace-pre">
+                <code>{highlight_code(lang, code)}</code>
+            </pre>
+            <button
+                {onclick}
+                class="absolute top-1 right-1 text-xs bg-gray-700 text-white rounded px-2 py-0.5 opacity-0 group-hover:opacity-100"
+                title="Copy code"
+            >
+                {"Copy"}
+            </button>
+        </div>
+    }
+}
+
+/// Writes `text` to the system clipboard without caring whether it
+/// succeeded — used for the code-block "Copy" button, which has no success
+/// feedback of its own to drive. See [`copy_to_clipboard_with`].
+fn copy_to_clipboard(text: &str) {
+    copy_to_clipboard_with(text, |_| {});
+}
+
+/// Writes `text` to the system clipboard via `navigator.clipboard`, falling
+/// back to a hidden textarea selection (the pre-Clipboard-API trick, still
+/// needed on insecure/`http://` origins where `navigator.clipboard` isn't
+/// exposed at all) if that API is unavailable or its write promise rejects
+/// — e.g. no clipboard permission grant. `on_done` is called once with
+/// whether the copy ultimately succeeded, after the (possibly asynchronous)
+/// clipboard write and/or fallback finish.
+fn copy_to_clipboard_with(text: &str, on_done: impl FnOnce(bool) + 'static) {
+    let Some(clipboard) = window().and_then(|w| w.navigator().clipboard()) else {
+        log::warn!("navigator.clipboard unavailable (insecure context?), falling back to textarea selection");
+        on_done(copy_via_textarea_selection(text));
+        return;
+    };
+    let promise = clipboard.write_text(text);
+    let text = text.to_string();
+    spawn_local(async move {
+        match wasm_bindgen_futures::JsFuture::from(promise).await {
+            Ok(_) => on_done(true),
+            Err(e) => {
+                log::warn!("clipboard write rejected, falling back to textarea selection: {:?}", e);
+                on_done(copy_via_textarea_selection(&text));
+            }
+        }
+    });
+}
+
+/// Pre-Clipboard-API fallback used by [`copy_to_clipboard_with`]: creates an
+/// off-screen textarea, selects its contents, and runs the deprecated but
+/// still broadly supported `execCommand("copy")`. Returns whether it
+/// reported success.
+fn copy_via_textarea_selection(text: &str) -> bool {
+    let Some(document) = window().and_then(|w| w.document()) else {
+        return false;
+    };
+    let Some(textarea) = document
+        .create_element("textarea")
+        .ok()
+        .and_then(|e| e.dyn_into::<web_sys::HtmlTextAreaElement>().ok())
+    else {
+        return false;
+    };
+    textarea.set_value(text);
+    let style = textarea.style();
+    let _ = style.set_property("position", "fixed");
+    let _ = style.set_property("left", "-9999px");
+    let Some(body) = document.body() else {
+        return false;
+    };
+    if body.append_child(&textarea).is_err() {
+        return false;
+    }
+    textarea.select();
+    let copied = document.exec_command("copy").unwrap_or(false);
+    let _ = textarea.remove();
+    copied
+}
+
+/// Copies `message` to the clipboard and, once the copy settles, flashes the
+/// clicked button's own label — "Copied!" on success or "Copy failed"
+/// otherwise — for a second before restoring it. Reusing the button's own
+/// label rather than a separate tooltip element keeps this feedback
+/// transient and stateless, the same way [`scroll_to_message`] flashes a
+/// highlight ring via a bare `gloo_timers` callback instead of round-tripping
+/// through component state for something this short-lived.
+fn copy_message_to_clipboard(e: MouseEvent, message: &str) {
+    let button = e.target().and_then(|t| t.dyn_into::<web_sys::HtmlElement>().ok());
+    copy_to_clipboard_with(message, move |ok| {
+        let Some(button) = button else { return };
+        let original = button.text_content().unwrap_or_default();
+        button.set_text_content(Some(if ok { "Copied!" } else { "Copy failed" }));
+        gloo_timers::callback::Timeout::new(1_500, move || {
+            button.set_text_content(Some(&original));
+        })
+        .forget();
+    });
+}
+
+/// Keyword set used by [`highlight_code`]'s highlighter for `lang` (matched
+/// case-insensitively against common names and file extensions), or an
+/// empty slice for an unrecognized or absent language — the code still
+/// renders, just without keyword coloring.
+fn keywords_for(lang: &str) -> &'static [&'static str] {
+    match lang.trim().to_ascii_lowercase().as_str() {
+        "rust" | "rs" => &[
+            "fn", "let", "mut", "pub", "struct", "enum", "impl", "trait", "match", "if", "else", "for", "while",
+            "loop", "return", "use", "mod", "self", "Self", "const", "static", "async", "await", "move", "as", "in",
+            "where", "dyn", "true", "false",
+        ],
+        "javascript" | "js" | "typescript" | "ts" | "jsx" | "tsx" => &[
+            "function", "let", "const", "var", "if", "else", "for", "while", "return", "class", "extends", "new",
+            "this", "import", "export", "default", "async", "await", "true", "false", "null", "undefined",
+        ],
+        "python" | "py" => &[
+            "def", "class", "if", "elif", "else", "for", "while", "return", "import", "from", "as", "with", "try",
+            "except", "finally", "lambda", "None", "True", "False", "self",
+        ],
+        _ => &[],
+    }
+}
+
+/// Splits `code` into `(text, css_class)` runs: line comments (`//` or `#`
+/// to end of line), quoted strings, numbers, and `keywords` get a color
+/// class, everything else (identifiers, punctuation, whitespace) is left
+/// plain. This is a small hand-rolled scanner rather than a real lexer or
+/// `syntect` — good enough to make pasted code readable without pulling in
+/// a grammar engine that doesn't target `wasm32` anyway.
+fn tokenize_code<'a>(code: &'a str, keywords: &[&str]) -> Vec<(&'a str, &'static str)> {
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < code.len() {
+        let rest = &code[i..];
+        let ch = rest.chars().next().expect("i < code.len()");
+        if rest.starts_with("//") || rest.starts_with('#') {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            tokens.push((&rest[..end], "text-gray-400 italic"));
+            i += end;
+        } else if ch == '"' || ch == '\'' {
+            let mut end = ch.len_utf8();
+            let mut escaped = false;
+            for c in rest[end..].chars() {
+                let byte_len = c.len_utf8();
+                end += byte_len;
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' {
+                    escaped = true;
+                } else if c == ch {
+                    break;
+                }
+            }
+            tokens.push((&rest[..end], "text-green-400"));
+            i += end;
+        } else if ch.is_ascii_digit() {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push((&rest[..end], "text-purple-400"));
+            i += end;
+        } else if ch.is_alphabetic() || ch == '_' {
+            let end = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            let word = &rest[..end];
+            tokens.push((word, if keywords.contains(&word) { "text-blue-400 font-semibold" } else { "" }));
+            i += end;
+        } else {
+            let end = ch.len_utf8();
+            tokens.push((&rest[..end], ""));
+            i += end;
+        }
+    }
+    tokens
+}
+
+/// Renders `code` (in `lang`, or unhighlighted if `lang` isn't recognized)
+/// as a sequence of colored spans, preserving every character — including
+/// indentation and blank lines — exactly as written.
+fn highlight_code(lang: &str, code: &str) -> Html {
+    let keywords = keywords_for(lang);
+    let spans = tokenize_code(code, keywords).into_iter().map(|(text, class)| {
+        if class.is_empty() {
+            html! { {text.to_string()} }
+        } else {
+            html! { <span class={class}>{text.to_string()}</span> }
+        }
+    });
+    html! { <>{ for spans }</> }
+}
+
+/// Only `http(s)`/`mailto` links render as clickable — anything else
+/// (notably `javascript:`) is dropped to plain text so a crafted message
+/// link can't run script on click.
+fn safe_href(dest: &str) -> Option<String> {
+    let lower = dest.trim().to_ascii_lowercase();
+    (lower.starts_with("http://") || lower.starts_with("https://") || lower.starts_with("mailto:"))
+        .then(|| dest.to_string())
+}
+
+/// Longest URL display text before [`truncate_url_for_display`] shortens it
+/// with an ellipsis — the link still opens at the full address, this just
+/// keeps a long one from blowing out a chat bubble's width.
+const URL_DISPLAY_MAX_LEN: usize = 40;
+
+/// If `word` (one whitespace-separated token from a message) starts with a
+/// bare `http://`/`https://` URL, returns it with trailing punctuation
+/// that's more likely to end a sentence than be part of the URL (a
+/// closing `.`, `,`, `)`, `"`, ...) split off, plus whatever's left over —
+/// mirroring how [`Chat::render_word`] splits an `@mention` from its
+/// trailing punctuation.
+fn linkify(word: &str) -> Option<(String, String)> {
+    let lower = word.to_ascii_lowercase();
+    if !(lower.starts_with("http://") || lower.starts_with("https://")) {
+        return None;
+    }
+    let trimmed = word.trim_end_matches(|c: char| matches!(c, '.' | ',' | '!' | '?' | ')' | ']' | '"' | '\''));
+    (!trimmed.is_empty()).then(|| (trimmed.to_string(), word[trimmed.len()..].to_string()))
+}
+
+/// Shortens `url` to [`URL_DISPLAY_MAX_LEN`] characters plus an ellipsis for
+/// display; the `href` it's wrapped in stays the full, untouched address.
+fn truncate_url_for_display(url: &str) -> String {
+    if url.chars().count() <= URL_DISPLAY_MAX_LEN {
+        return url.to_string();
+    }
+    let truncated: String = url.chars().take(URL_DISPLAY_MAX_LEN).collect();
+    format!("{}…", truncated)
+}
+
+/// Extensions that render inline as an `<img>` when a message is *entirely*
+/// a link to one, rather than going through [`Chat::render_message_body`]
+/// as ordinary text — a lone image URL is almost always meant to be seen,
+/// not read.
+const INLINE_IMAGE_EXTENSIONS: [&str; 5] = [".gif", ".png", ".jpg", ".jpeg", ".webp"];
+
+/// Whether `message` is entirely a link to one of [`INLINE_IMAGE_EXTENSIONS`].
+fn is_inline_image_url(message: &str) -> bool {
+    let lower = message.to_ascii_lowercase();
+    INLINE_IMAGE_EXTENSIONS.iter().any(|ext| lower.ends_with(ext))
+}
+
+/// Renders one structured [`Attachment`], dispatching on its kind instead of
+/// sniffing the message text the way [`is_inline_image_url`] has to for a
+/// bare pasted link.
+fn render_attachment(attachment: &Attachment) -> Html {
+    match attachment {
+        Attachment::Image { url, alt } => html! {
+            <img class="mt-1 max-w-xs rounded" src={url.clone()} alt={alt.clone()} />
+        },
+        Attachment::File { url, name, size } => html! {
+            <a href={url.clone()} target="_blank" rel="noopener noreferrer" class="mt-1 flex items-center gap-2 text-xs underline">
+                <span>{"📎"}</span>
+                <span>{name.clone()}</span>
+                <span class="text-gray-400">{format!("({} bytes)", size)}</span>
+            </a>
+        },
+        Attachment::Link { url, title } => html! {
+            <a href={url.clone()} target="_blank" rel="noopener noreferrer" class="mt-1 block text-xs underline truncate max-w-xs">
+                {title.clone()}
+            </a>
+        },
+    }
+}
+
+/// Renders `text` as plain text with every case-insensitive occurrence of
+/// `query` wrapped in a `<mark>`, used in place of the full Markdown
+/// renderer while a search is active — search matches on the raw text, so
+/// highlighting the rendered-Markdown output could point at the wrong
+/// characters.
+fn highlight_matches(text: &str, query: &str) -> Html {
+    if query.is_empty() {
+        return html! { {text} };
+    }
+    let lower_text = text.to_lowercase();
+    let lower_query = query.to_lowercase();
+    let mut spans: Vec<Html> = Vec::new();
+    let mut rest = text;
+    let mut lower_rest = lower_text.as_str();
+    while let Some(pos) = lower_rest.find(&lower_query) {
+        let (before, after) = rest.split_at(pos);
+        let (matched, remainder) = after.split_at(lower_query.len());
+        if !before.is_empty() {
+            spans.push(html! { {before} });
+        }
+        spans.push(html! { <mark class="bg-yellow-300">{matched}</mark> });
+        rest = remainder;
+        lower_rest = &lower_rest[pos + lower_query.len()..];
+    }
+    spans.push(html! { {rest} });
+    html! { <>{ for spans }</> }
+}
+
+impl Component for Chat {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let (user, _) = ctx
+            .link()
+            .context::<User>(Callback::noop())
+            .expect("context to be set");
+        let (wss_handle, _) = ctx
+            .link()
+            .context::<WsHandle>(Callback::noop())
+            .expect("WsHandle context to be set");
+        let url = config::resolve_ws_url();
+        let config_error = url.as_ref().err().cloned();
+        let url = url.unwrap_or_else(|_| config::DEFAULT_WS_URL.to_string());
+        let wss = wss_handle.connect(url, user.token.borrow().clone());
+        let connection_state = wss.state();
+        let username = user.username.borrow().clone();
+        let current_room = default_room();
+
+        send_register(&wss, &username, &current_room);
+        send_history_request(&wss, &current_room, None);
+
+        let prune_link = ctx.link().clone();
+        let typing_prune_interval = gloo_timers::callback::Interval::new(1_000, move || {
+            prune_link.send_message(Msg::PruneTypingIndicators);
+        });
+
+        let visibility_link = ctx.link().clone();
+        let visibility_listener = Closure::<dyn Fn()>::new(move || {
+            visibility_link.send_message(Msg::VisibilityChanged);
+        });
+        if let Some(document) = window().and_then(|w| w.document()) {
+            if let Err(e) = document.add_event_listener_with_callback(
+                "visibilitychange",
+                visibility_listener.as_ref().unchecked_ref(),
+            ) {
+                log::error!("failed to attach visibilitychange listener: {:?}", e);
+            }
+        }
+
+        let activity_link = ctx.link().clone();
+        let activity_listener = Closure::<dyn Fn()>::new(move || {
+            activity_link.send_message(Msg::UserActivity);
+        });
+        if let Some(document) = window().and_then(|w| w.document()) {
+            for event in ["pointermove", "keydown"] {
+                if let Err(e) = document.add_event_listener_with_callback(event, activity_listener.as_ref().unchecked_ref()) {
+                    log::error!("failed to attach {} listener: {:?}", event, e);
+                }
+            }
+        }
+
+        let presence_tick_link = ctx.link().clone();
+        let presence_tick_interval = gloo_timers::callback::Interval::new(PRESENCE_TICK_MS, move || {
+            presence_tick_link.send_message(Msg::PresenceTick);
+        });
+
+        let messages = load_message_history();
+        let seen_message_ids = messages.values().flatten().filter_map(|m| m.id.clone()).collect();
+
+        Self {
+            users: std::collections::HashMap::new(),
+            user_order: Vec::new(),
+            offline_since: std::collections::HashMap::new(),
+            messages,
+            system_events: std::collections::HashMap::new(),
+            history_loading: std::collections::HashSet::from([current_room.clone()]),
+            history_loaded: std::collections::HashSet::from([current_room.clone()]),
+            history_exhausted: std::collections::HashSet::new(),
+            pending_scroll_restore: None,
+            seen_message_ids,
+            pending_deletes: std::collections::HashSet::new(),
+            pending_reactions: Vec::new(),
+            message_cap: DEFAULT_MESSAGE_CAP,
+            pinned: Vec::new(),
+            pinned_strip_open: false,
+            pending_pin_reverts: std::collections::HashMap::new(),
+            chat_input: NodeRef::default(),
+            image_input: NodeRef::default(),
+            search_input: NodeRef::default(),
+            messages_container: NodeRef::default(),
+            should_stick_to_bottom: true,
+            missed_while_scrolled: 0,
+            unread_divider_after: None,
+            unread_divider_clear_at: None,
+            wss,
+            wss_handle,
+            connection_state,
+            reconnect_attempt: 0,
+            registered: false,
+            username,
+            config_error,
+            typing_users: std::collections::HashMap::new(),
+            last_typing_sent_at: 0.0,
+            _typing_prune_interval: typing_prune_interval,
+            read_up_to: std::collections::HashMap::new(),
+            last_read_id_sent: None,
+            last_read_sent_at: 0.0,
+            editing_id: None,
+            compose_len: 0,
+            notices: vec![],
+            replying_to: None,
+            ephemeral_ttl: None,
+            current_room,
+            unread_counts: std::collections::HashMap::new(),
+            active_dm: None,
+            dm_messages: std::collections::HashMap::new(),
+            open_dm_tabs: vec![],
+            dm_unread: std::collections::HashMap::new(),
+            thread_replies: std::collections::HashMap::new(),
+            open_thread: None,
+            thread_input: NodeRef::default(),
+            metrics: None,
+            upgrade_required: None,
+            unread: 0,
+            mentions_count: 0,
+            _visibility_listener: visibility_listener,
+            _activity_listener: activity_listener,
+            last_activity_at: Date::now(),
+            self_presence: UserStatus::Online,
+            _presence_tick_interval: presence_tick_interval,
+            user,
+            sound_enabled: load_sound_enabled(),
+            last_sound_at: 0.0,
+            theme: load_theme(),
+            emoji_picker_open: false,
+            emoji_picker_focus: 0,
+            recent_emojis: load_recent_emojis(),
+            search_open: false,
+            search_query: String::new(),
+            search_match_index: None,
+            avatar_provider: select_avatar_provider(),
+            _producer: EventBus::bridge(ctx.link().callback(Msg::HandleMsg)),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            Msg::HandleMsg(Delivery { message: BusMessage::ParseError(e), .. }) => {
+                self.push_notice(format!("Received a malformed message from the server: {}", e));
+                true
+            }
+            Msg::HandleMsg(Delivery { message: BusMessage::Frame(msg), replayed }) => {
+                match msg.message_type {
                     MsgTypes::Users => {
-                        let users_from_message = msg.data_array.unwrap_or_default();
-                        self.users = users_from_message
+                        let profiles: Vec<UserProfile> = msg
+                            .data_array
+                            .unwrap_or_default()
                             .iter()
-                            .map(|u| UserProfile {
-                                name: u.into(),
-                                avatar: format!(
-                                    "https://avatars.dicebear.com/api/adventurer-neutral/{}.svg",
-                                    u
-                                )
-                                .into(),
-                            })
+                            .map(|u| parse_roster_entry(u, self.avatar_provider.as_ref()))
+                            .collect();
+                        let present: std::collections::HashSet<String> =
+                            profiles.iter().map(|p| p.name.clone()).collect();
+                        let now = Date::now();
+                        let previously_known: std::collections::HashSet<String> =
+                            self.users.keys().cloned().collect();
+                        // Names in this roster nobody's seen before — i.e.
+                        // not even remembered as `Offline` — get a "joined"
+                        // notice; see the drop side of the diff below for
+                        // "left". Skipped on the very first roster we ever
+                        // see (after connecting, or after a reconnect reset
+                        // `users`), since that's everyone already here, not
+                        // a wave of new arrivals.
+                        if !previously_known.is_empty() {
+                            for name in &present {
+                                if name != &self.username && !previously_known.contains(name) {
+                                    self.system_events.entry(self.current_room.clone()).or_default().push(SystemEvent {
+                                        text: format!("{} joined", name),
+                                        timestamp: now,
+                                    });
+                                }
+                            }
+                        }
+                        // Anyone who dropped out of this roster is kept
+                        // around as `Offline` instead of disappearing
+                        // outright — `Msg::PresenceTick` removes them for
+                        // good once `OFFLINE_RETENTION_MS` has passed.
+                        let mut stragglers = Vec::new();
+                        for name in &self.user_order {
+                            if present.contains(name) {
+                                continue;
+                            }
+                            if let Some(mut profile) = self.users.get(name).cloned() {
+                                profile.status = UserStatus::Offline;
+                                if name != &self.username && !self.offline_since.contains_key(name) {
+                                    self.system_events.entry(self.current_room.clone()).or_default().push(SystemEvent {
+                                        text: format!("{} left", name),
+                                        timestamp: now,
+                                    });
+                                }
+                                self.offline_since.entry(name.clone()).or_insert(now);
+                                stragglers.push(profile);
+                            }
+                        }
+                        self.offline_since.retain(|name, _| !present.contains(name));
+                        self.user_order = profiles
+                            .iter()
+                            .map(|p| p.name.clone())
+                            .chain(stragglers.iter().map(|p| p.name.clone()))
+                            .collect();
+                        self.users = profiles
+                            .into_iter()
+                            .chain(stragglers)
+                            .map(|p| (p.name.clone(), p))
                             .collect();
                         return true;
                     }
                     MsgTypes::Message => {
-                        let message_data: MessageData =
-                            serde_json::from_str(&msg.data.unwrap()).unwrap();
-                        self.messages.push(message_data);
+                        let Some(data) = msg.data else {
+                            log::warn!("message event had no data payload");
+                            return false;
+                        };
+                        let mut message_data: MessageData = match serde_json::from_str(&data) {
+                            Ok(message_data) => message_data,
+                            Err(e) => {
+                                log::warn!("dropping malformed message payload {:?}: {}", data, e);
+                                return false;
+                            }
+                        };
+                        if !self.seen_message_ids.insert(dedupe_key(&message_data)) {
+                            // Redelivered after a reconnect (or a replayed
+                            // event we already folded in) — this id (or, for
+                            // the old id-less protocol, this sender+content
+                            // pair) is already in `self.seen_message_ids`,
+                            // so drop this copy instead of showing it twice.
+                            return false;
+                        }
+                        if let Some(id) = message_data.id.clone() {
+                            if self.pending_deletes.remove(&id) {
+                                message_data.message = DELETED_PLACEHOLDER.to_string();
+                                message_data.deleted = true;
+                                message_data.image_url = None;
+                            }
+                            apply_pending_reactions(&mut self.pending_reactions, std::slice::from_mut(&mut message_data), &id, Date::now());
+                        }
+                        if message_data.timestamp.is_none() {
+                            message_data.timestamp = Some(Date::now());
+                        }
+                        if let Some(seconds) = message_data.expires_in {
+                            // Computed from *our* receive time, not carried
+                            // over the wire — see `MessageData::expires_at`.
+                            message_data.expires_at = Some(Date::now() + (seconds as f64) * 1000.0);
+                        }
+                        if let Some(root) = message_data.thread_root.clone() {
+                            // Thread replies never show up inline — fold it
+                            // into its root's thread instead of `messages`
+                            // so the "N replies" count stays accurate even
+                            // while that thread's panel is closed, and skip
+                            // the mention/sound/unread handling below since
+                            // none of that makes sense for a reply buried
+                            // in a thread nobody has open.
+                            self.thread_replies.entry(root).or_default().push(message_data);
+                            return true;
+                        }
+                        // A replayed event already happened before this
+                        // component existed to react to it — rebuild state
+                        // from it below, but skip everything that's only
+                        // meaningful on arrival (sounds, flashes, badges).
+                        let is_mention = !replayed
+                            && message_data.from != self.username
+                            && mentions(&message_data.message, &self.username);
+                        if is_mention {
+                            flash_title(&format!("{} mentioned you", message_data.from));
+                            self.mentions_count += 1;
+                        }
+                        // A mention plays the notification sound even with
+                        // the mute toggle on — muting is for the general
+                        // chatter, not for someone calling you out directly.
+                        if !replayed && message_data.from != self.username && (self.sound_enabled || is_mention) {
+                            self.last_sound_at = play_message_sound(self.last_sound_at);
+                        }
+                        if !replayed && message_data.from != self.username && document_hidden() {
+                            self.unread += 1;
+                            set_unread_title(self.unread);
+                            if self.user.notifications_enabled.get() {
+                                show_notification(&message_data.from, &message_data.message);
+                            }
+                        }
+                        if let Some(to) = message_data.to.clone() {
+                            let peer = if message_data.from == self.username { to } else { message_data.from.clone() };
+                            if !self.open_dm_tabs.contains(&peer) {
+                                self.open_dm_tabs.push(peer.clone());
+                            }
+                            if !replayed && self.active_dm.as_deref() != Some(peer.as_str()) && message_data.from != self.username {
+                                *self.dm_unread.entry(peer.clone()).or_insert(0) += 1;
+                                flash_title(&format!("{} sent you a message", message_data.from));
+                            }
+                            self.note_arrival(self.active_dm.as_deref() == Some(peer.as_str()));
+                            self.dm_messages.entry(peer).or_default().push(message_data);
+                            return true;
+                        }
+                        if message_data.room != self.current_room {
+                            *self.unread_counts.entry(message_data.room.clone()).or_insert(0) += 1;
+                        }
+                        self.note_arrival(self.active_dm.is_none() && message_data.room == self.current_room);
+                        let cap = self.message_cap;
+                        let room = message_data.room.clone();
+                        let evicted = push_bounded_messages(self.room_messages_mut(&room), cap, message_data);
+                        for evicted in &evicted {
+                            self.seen_message_ids.remove(&dedupe_key(evicted));
+                        }
+                        self.persist_messages();
+                        return true;
+                    }
+                    MsgTypes::SendFailure => {
+                        let reason = msg.data.unwrap_or_default();
+                        log::warn!("send failure reported by websocket service: {}", reason);
+                        if !replayed {
+                            self.push_notice(reason);
+                        }
+                        return true;
+                    }
+                    MsgTypes::Typing => {
+                        let Some(from) = msg.data else { return false };
+                        if from == self.username {
+                            return false;
+                        }
+                        self.typing_users.insert(from, Date::now());
+                        return true;
+                    }
+                    MsgTypes::Read => {
+                        let (Some(user), Some(id)) = (msg.data, msg.id) else {
+                            log::warn!("read receipt missing user or message id");
+                            return false;
+                        };
+                        if user == self.username {
+                            return false;
+                        }
+                        self.read_up_to.insert(user, id);
+                        return true;
+                    }
+                    MsgTypes::Ack => {
+                        let Some(id) = msg.id else {
+                            log::warn!("ack frame had no target id");
+                            return false;
+                        };
+                        match self.find_message_mut(&id) {
+                            Some(m) => {
+                                m.pending = false;
+                                m.status = MessageStatus::Delivered;
+                                true
+                            }
+                            None => false,
+                        }
+                    }
+                    MsgTypes::Image => {
+                        let Some(data) = msg.data else {
+                            log::warn!("image event had no data payload");
+                            return false;
+                        };
+                        let bytes = match base64::decode(&data) {
+                            Ok(bytes) => bytes,
+                            Err(e) => {
+                                log::warn!("dropping malformed image payload: {}", e);
+                                return false;
+                            }
+                        };
+                        let image_url = match blob_url_for(&bytes) {
+                            Ok(url) => url,
+                            Err(e) => {
+                                log::warn!("failed to build object URL for image: {:?}", e);
+                                return false;
+                            }
+                        };
+                        self.note_arrival(self.active_dm.is_none() && msg.room == self.current_room);
+                        let cap = self.message_cap;
+                        let evicted = push_bounded_messages(
+                            self.room_messages_mut(&msg.room),
+                            cap,
+                            MessageData {
+                                id: None,
+                                from: "image".to_string(),
+                                message: String::new(),
+                                attachments: Vec::new(),
+                                timestamp: Some(Date::now()),
+                                pending: false,
+                                failed: false,
+                                status: MessageStatus::Delivered,
+                                edited: false,
+                                deleted: false,
+                                pinned: false,
+                                reactions: std::collections::HashMap::new(),
+                                reply_to: None,
+                                reply_snippet: None,
+                                thread_root: None,
+                                to: msg.to.clone(),
+                                recipients: Vec::new(),
+                                expires_in: None,
+                                expires_at: None,
+                                room: msg.room.clone(),
+                                image_url: Some(image_url),
+                            },
+                        );
+                        for evicted in &evicted {
+                            self.seen_message_ids.remove(&dedupe_key(evicted));
+                        }
+                        self.persist_messages();
+                        return true;
+                    }
+                    MsgTypes::Edit => {
+                        let Some(id) = msg.id else {
+                            log::warn!("edit event had no target id");
+                            return false;
+                        };
+                        let Some(new_text) = msg.data else {
+                            log::warn!("edit event had no replacement text");
+                            return false;
+                        };
+                        match self.messages.values_mut().find_map(|room| room.iter_mut().find(|m| m.id.as_deref() == Some(id.as_str()))) {
+                            Some(m) => {
+                                m.message = new_text;
+                                m.edited = true;
+                                self.persist_messages();
+                                return true;
+                            }
+                            None => {
+                                log::warn!("edit for unknown message id {}", id);
+                                return false;
+                            }
+                        }
+                    }
+                    MsgTypes::Delete => {
+                        let Some(id) = msg.id else {
+                            log::warn!("delete event had no target id");
+                            return false;
+                        };
+                        if !self.messages.values_mut().any(|room| delete_message(room, &id)) {
+                            // The message hasn't arrived yet — remember the
+                            // delete and apply it once it does, instead of
+                            // dropping it on the floor.
+                            self.pending_deletes.insert(id);
+                        }
+                        self.persist_messages();
                         return true;
                     }
-                    _ => {
+                    MsgTypes::React => {
+                        let Some(id) = msg.id else {
+                            log::warn!("reaction event had no target id");
+                            return false;
+                        };
+                        let Some(data) = msg.data else {
+                            log::warn!("reaction event had no payload");
+                            return false;
+                        };
+                        let payload: ReactionPayload = match serde_json::from_str(&data) {
+                            Ok(payload) => payload,
+                            Err(e) => {
+                                log::warn!("dropping malformed reaction payload {:?}: {}", data, e);
+                                return false;
+                            }
+                        };
+                        if !self.messages.values_mut().any(|room| toggle_reaction(room, &id, &payload.emoji, &payload.user)) {
+                            // Might just be racing the `Message` it targets —
+                            // buffer it and give that a chance to arrive.
+                            self.pending_reactions.retain(|(_, _, queued_at)| Date::now() - queued_at <= REACTION_BUFFER_MS);
+                            self.pending_reactions.push((id, payload, Date::now()));
+                        }
+                        return true;
+                    }
+                    MsgTypes::Pin => {
+                        let Some(id) = msg.id else {
+                            log::warn!("pin event had no target id");
+                            return false;
+                        };
+                        let Some(data) = msg.data else {
+                            log::warn!("pin event had no payload");
+                            return false;
+                        };
+                        let payload: PinPayload = match serde_json::from_str(&data) {
+                            Ok(payload) => payload,
+                            Err(e) => {
+                                log::warn!("dropping malformed pin payload {:?}: {}", data, e);
+                                return false;
+                            }
+                        };
+                        if let Some(m) = self.messages.values_mut().find_map(|room| room.iter_mut().find(|m| m.id.as_deref() == Some(id.as_str()))) {
+                            m.pinned = true;
+                        }
+                        // Unlike a delete or reaction, there's nothing to
+                        // buffer if the message hasn't arrived yet — the
+                        // strip renders straight from `self.pinned`'s own
+                        // snippet, so it's already correct either way; only
+                        // the bubble's highlight waits for the message itself.
+                        if !self.pinned.iter().any(|p| p.id == id) {
+                            self.pinned.push(PinnedMessage { id, snippet: payload.snippet });
+                        }
+                        return true;
+                    }
+                    MsgTypes::Unpin => {
+                        let Some(id) = msg.id else {
+                            log::warn!("unpin event had no target id");
+                            return false;
+                        };
+                        if let Some(m) = self.messages.values_mut().find_map(|room| room.iter_mut().find(|m| m.id.as_deref() == Some(id.as_str()))) {
+                            m.pinned = false;
+                        }
+                        self.pinned.retain(|p| p.id != id);
+                        return true;
+                    }
+                    MsgTypes::Presence => {
+                        let Some(data) = msg.data else {
+                            log::warn!("presence event had no payload");
+                            return false;
+                        };
+                        let payload: PresencePayload = match serde_json::from_str(&data) {
+                            Ok(payload) => payload,
+                            Err(e) => {
+                                log::warn!("dropping malformed presence payload {:?}: {}", data, e);
+                                return false;
+                            }
+                        };
+                        if payload.user == self.username {
+                            // We already set our own status locally the
+                            // moment it changed — no need to wait for our
+                            // own frame to echo back.
+                            return false;
+                        }
+                        let Some(profile) = self.users.get_mut(&payload.user) else {
+                            return false;
+                        };
+                        profile.status = payload.status;
+                        if payload.status == UserStatus::Offline {
+                            self.offline_since.entry(payload.user).or_insert(Date::now());
+                        } else {
+                            self.offline_since.remove(&payload.user);
+                        }
+                        return true;
+                    }
+                    MsgTypes::History => {
+                        self.history_loading.remove(&msg.room);
+                        let raw = msg.data_array.unwrap_or_default();
+                        if raw.len() < HISTORY_REQUEST_LIMIT as usize {
+                            // A short page means the server has nothing
+                            // older left to give — stop asking and show the
+                            // "beginning of conversation" marker instead of
+                            // a spinner next time this room scrolls to the top.
+                            self.history_exhausted.insert(msg.room.clone());
+                        }
+                        let mut entries: Vec<MessageData> = raw
+                            .iter()
+                            .filter_map(|raw| match serde_json::from_str::<MessageData>(raw) {
+                                Ok(m) => Some(m),
+                                Err(e) => {
+                                    log::warn!("dropping malformed history payload {:?}: {}", raw, e);
+                                    None
+                                }
+                            })
+                            .filter(|m| !self.seen_message_ids.contains(&dedupe_key(m)))
+                            .collect();
+                        if entries.is_empty() {
+                            return true;
+                        }
+                        entries.sort_by(|a, b| a.timestamp.partial_cmp(&b.timestamp).unwrap_or(std::cmp::Ordering::Equal));
+                        for m in &entries {
+                            self.seen_message_ids.insert(dedupe_key(m));
+                        }
+                        if msg.before.is_none() {
+                            // Only the first page of a room's backlog gets
+                            // this divider — later pages are just more
+                            // backlog, prepended above it, not a second
+                            // boundary between "history" and "live".
+                            let earliest_ts = entries.first().and_then(|m| m.timestamp).unwrap_or_else(Date::now);
+                            self.system_events.entry(msg.room.clone()).or_default().insert(
+                                0,
+                                SystemEvent {
+                                    text: "—— earlier messages ——".to_string(),
+                                    timestamp: earliest_ts - 1.0,
+                                },
+                            );
+                        }
+                        if msg.room == self.current_room && self.active_dm.is_none() {
+                            // Snapshot the scroll position now, before the
+                            // prepend below changes `scroll_height` out from
+                            // under the reader — `rendered` uses the delta
+                            // to keep whatever they were looking at in place.
+                            if let Some(container) = self.messages_container.cast::<HtmlElement>() {
+                                self.pending_scroll_restore =
+                                    Some((container.scroll_height() as f64, container.scroll_top() as f64));
+                            }
+                        }
+                        let room_messages = self.room_messages_mut(&msg.room);
+                        entries.append(room_messages);
+                        *room_messages = entries;
+                        self.persist_messages();
+                        return true;
+                    }
+                    MsgTypes::ConnectionState => {
+                        // No need to re-send `Register` here on a
+                        // reconnect — `WebsocketService` remembers the
+                        // last payload passed to `register` and resends it
+                        // on every connection by itself.
+                        self.connection_state = match msg.data.as_deref() {
+                            Some("connecting") => ConnectionState::Connecting,
+                            Some("open") => ConnectionState::Open,
+                            Some("reconnecting") => ConnectionState::Reconnecting,
+                            Some("unauthorized") => ConnectionState::Unauthorized,
+                            Some("failed") => ConnectionState::Failed,
+                            _ => ConnectionState::Closed,
+                        };
+                        self.reconnect_attempt = msg.attempt.unwrap_or(0);
+                        if self.connection_state != ConnectionState::Open {
+                            // Still waiting on a fresh `Register` ack for
+                            // this connection — `WebsocketService` resends
+                            // `Register` on every reconnect by itself, but
+                            // we don't hear back until the server processes it.
+                            self.registered = false;
+                        }
+                        if self.connection_state == ConnectionState::Closed {
+                            // The roster we're holding is from the
+                            // connection that just dropped — better to show
+                            // nobody than a list that might already be
+                            // wrong until `Register` lands again post-reconnect.
+                            self.users.clear();
+                            self.user_order.clear();
+                        }
+                        return true;
+                    }
+                    MsgTypes::Register => {
+                        self.registered = true;
+                        return true;
+                    }
+                    MsgTypes::Error => {
+                        let Some(data) = msg.data else {
+                            log::warn!("error event had no data payload");
+                            return false;
+                        };
+                        let payload = match serde_json::from_str::<ServerErrorPayload>(&data) {
+                            Ok(payload) => payload,
+                            Err(e) => {
+                                log::warn!("dropping malformed error payload {:?}: {}", data, e);
+                                return false;
+                            }
+                        };
+                        self.push_notice(payload.message);
+                        if let Some(m) = payload.ref_id.as_ref().and_then(|id| self.find_message_mut(id)) {
+                            m.pending = false;
+                            m.failed = true;
+                        }
+                        // Independent of the send-failure revert above — a
+                        // rejected `Pin`/`Unpin` names the same message id in
+                        // `ref_id`, so undo whichever optimistic toggle is
+                        // still outstanding for it.
+                        if let Some(ref_id) = payload.ref_id.clone() {
+                            if let Some(previous_pinned) = self.pending_pin_reverts.remove(&ref_id) {
+                                if let Some(m) = self.find_message_mut(&ref_id) {
+                                    m.pinned = previous_pinned;
+                                }
+                                if previous_pinned {
+                                    let snippet = self.find_message(&ref_id).map(reply_snippet_for).unwrap_or_default();
+                                    if !self.pinned.iter().any(|p| p.id == ref_id) {
+                                        self.pinned.push(PinnedMessage { id: ref_id, snippet });
+                                    }
+                                } else {
+                                    self.pinned.retain(|p| p.id != ref_id);
+                                }
+                            }
+                        }
+                        return true;
+                    }
+                    MsgTypes::Unknown => {
+                        log::warn!("dropping frame with an unrecognized message type: {:?}", msg.data);
                         return false;
                     }
+                    MsgTypes::Join | MsgTypes::Leave => {
+                        let Some(user) = msg.data else {
+                            log::warn!("join/leave event had no user payload");
+                            return false;
+                        };
+                        if user == self.username || replayed {
+                            // Our own room switches already update the UI
+                            // directly, and a replayed event only means we
+                            // reconnected into a room we were already in —
+                            // neither is something worth narrating.
+                            return false;
+                        }
+                        let verb = if msg.message_type == MsgTypes::Join { "joined" } else { "left" };
+                        let now = Date::now();
+                        let events = self.system_events.entry(msg.room).or_default();
+                        if let Some(last) = events.last_mut() {
+                            if last.text.starts_with(&format!("{} ", user)) && now - last.timestamp < SYSTEM_EVENT_COALESCE_MS {
+                                last.text = format!("{} {}", user, verb);
+                                last.timestamp = now;
+                                return true;
+                            }
+                        }
+                        events.push(SystemEvent {
+                            text: format!("{} {}", user, verb),
+                            timestamp: now,
+                        });
+                        return true;
+                    }
+                    MsgTypes::Stalled => {
+                        if !replayed {
+                            self.push_notice("Connection stalled — reconnecting…");
+                        }
+                        return true;
+                    }
+                    MsgTypes::Metrics => {
+                        let Some(data) = msg.data else {
+                            log::warn!("metrics event had no data payload");
+                            return false;
+                        };
+                        match serde_json::from_str::<ConnectionMetrics>(&data) {
+                            Ok(metrics) => {
+                                self.metrics = Some(metrics);
+                                return true;
+                            }
+                            Err(e) => {
+                                log::warn!("dropping malformed metrics payload {:?}: {}", data, e);
+                                return false;
+                            }
+                        }
+                    }
+                    MsgTypes::UpgradeRequired => {
+                        match parse_upgrade_required(msg.data.as_deref()) {
+                            HandshakeOutcome::UpgradeRequired { minimum_version } => {
+                                self.upgrade_required = Some(minimum_version);
+                                return true;
+                            }
+                            HandshakeOutcome::Accepted => return false,
+                        }
+                    }
                 }
             }
             Msg::SubmitMessage => {
                 let input = self.chat_input.cast::<HtmlInputElement>();
                 if let Some(input) = input {
+                    let text = input.value().trim().to_string();
+                    if let Some(id) = self.editing_id.take() {
+                        if text.is_empty() {
+                            // An empty edit isn't a way to delete a message —
+                            // that's what the delete button is for — so
+                            // reject it and leave the editor open instead of
+                            // silently sending a blank body.
+                            self.push_notice("Edited message can't be empty".to_string());
+                            self.editing_id = Some(id);
+                            return true;
+                        }
+                        if text.chars().count() > MAX_MESSAGE_LENGTH {
+                            self.push_notice(format!("Message is too long (max {} characters)", MAX_MESSAGE_LENGTH));
+                            self.editing_id = Some(id);
+                            return true;
+                        }
+                        let message = WebSocketMessage {
+                            data: Some(text.clone()),
+                            id: Some(id.clone()),
+                            room: self.current_room.clone(),
+                            ..WebSocketMessage::new(MsgTypes::Edit)
+                        };
+                        self.wss.send(message);
+                        if let Some(m) = self.find_message_mut(&id) {
+                            m.message = text;
+                            m.edited = true;
+                        }
+                        input.set_value("");
+                        self.compose_len = 0;
+                        return true;
+                    }
+
+                    if text.is_empty() {
+                        // Don't clear the box — a whitespace-only submit is
+                        // almost always a stray Enter, not an intent to send
+                        // nothing, so leave whatever the user typed in place.
+                        return true;
+                    }
+                    if text.chars().count() > MAX_MESSAGE_LENGTH {
+                        self.push_notice(format!("Message is too long (max {} characters)", MAX_MESSAGE_LENGTH));
+                        return true;
+                    }
+
+                    let text = match parse_command(&text) {
+                        Command::Me(action) => format!("*{} {}*", self.username, action),
+                        Command::Shrug(prefix) => {
+                            if prefix.is_empty() {
+                                SHRUG.to_string()
+                            } else {
+                                format!("{} {}", prefix, SHRUG)
+                            }
+                        }
+                        Command::Clear => {
+                            match &self.active_dm {
+                                Some(peer) => {
+                                    self.dm_messages.remove(peer);
+                                }
+                                None => {
+                                    self.room_messages_mut(&self.current_room.clone()).clear();
+                                    self.persist_messages();
+                                }
+                            }
+                            input.set_value("");
+                            self.compose_len = 0;
+                            return true;
+                        }
+                        Command::Unknown(raw) => {
+                            let command = raw.split_whitespace().next().unwrap_or(&raw).to_string();
+                            self.push_notice(format!("Unknown command: {}", command));
+                            return true;
+                        }
+                        Command::None => text,
+                    };
+
+                    let (recipients, text) = match parse_whisper(&text) {
+                        Some((recipients, message)) => (recipients, message),
+                        None => (Vec::new(), text),
+                    };
+                    let (expires_in, text) = match parse_ephemeral(&text) {
+                        Some((seconds, message)) => (Some(seconds), message),
+                        None => (self.ephemeral_ttl.take(), text),
+                    };
+                    let id = generate_id();
+                    let timestamp = Date::now();
+                    let reply_to = self.replying_to.take();
+                    let reply_snippet = reply_to.as_deref().and_then(|id| self.find_message(id)).map(reply_snippet_for);
                     let message = WebSocketMessage {
-                        message_type: MsgTypes::Message,
-                        data: Some(input.value()),
-                        data_array: None,
+                        data: Some(text.clone()),
+                        timestamp: Some(timestamp),
+                        id: Some(id.clone()),
+                        reply_to: reply_to.clone(),
+                        reply_snippet: reply_snippet.clone(),
+                        to: self.active_dm.clone(),
+                        recipients: recipients.clone(),
+                        expires_in,
+                        room: self.current_room.clone(),
+                        ..WebSocketMessage::new(MsgTypes::Message)
                     };
-                    if let Err(e) = self
-                        .wss
-                        .tx
-                        .clone()
-                        .try_send(serde_json::to_string(&message).unwrap())
-                    {
-                        log::debug!("error sending to channel: {:?}", e);
+                    let ack_future = self.wss.send_with_ack(id.clone(), message);
+                    // Show it locally right away instead of waiting on the
+                    // server's broadcast echo — `seen_message_ids` already
+                    // has `id` by the time that echo arrives, so it's
+                    // dropped as a duplicate instead of appearing twice.
+                    self.seen_message_ids.insert(id.clone());
+                    let pending_message = MessageData {
+                        id: Some(id.clone()),
+                        from: self.username.clone(),
+                        message: text,
+                        attachments: Vec::new(),
+                        timestamp: Some(timestamp),
+                        pending: true,
+                        failed: false,
+                        status: MessageStatus::Sending,
+                        edited: false,
+                        deleted: false,
+                        pinned: false,
+                        reactions: std::collections::HashMap::new(),
+                        reply_to,
+                        reply_snippet,
+                        thread_root: None,
+                        to: self.active_dm.clone(),
+                        recipients,
+                        expires_in,
+                        expires_at: expires_in.map(|seconds| timestamp + (seconds as f64) * 1000.0),
+                        room: self.current_room.clone(),
+                        image_url: None,
+                    };
+                    match &self.active_dm {
+                        Some(peer) => self.dm_messages.entry(peer.clone()).or_default().push(pending_message),
+                        None => {
+                            let cap = self.message_cap;
+                            let evicted =
+                                push_bounded_messages(self.room_messages_mut(&self.current_room.clone()), cap, pending_message);
+                            for evicted in &evicted {
+                                self.seen_message_ids.remove(&dedupe_key(evicted));
+                            }
+                            self.persist_messages();
+                        }
+                    }
+                    // A message we just sent ourselves should always land in
+                    // view, even if we'd scrolled up to read history —
+                    // unlike an incoming message from someone else, there's
+                    // no reason to think we'd rather keep reading up there.
+                    self.should_stick_to_bottom = true;
+                    self.mark_caught_up();
+                    let link = ctx.link().clone();
+                    spawn_local(async move {
+                        match ack_future.await {
+                            Ok(()) => link.send_message(Msg::MessageAcked(id)),
+                            Err(reason) => link.send_message(Msg::MessageAckFailed(id, reason)),
+                        }
+                    });
+                    input.set_value("");
+                    self.compose_len = 0;
+                    return true;
+                };
+                false
+            }
+            Msg::InputChanged => {
+                let len = self
+                    .chat_input
+                    .cast::<HtmlInputElement>()
+                    .map(|input| input.value().chars().count())
+                    .unwrap_or(0);
+                let len_changed = len != self.compose_len;
+                self.compose_len = len;
+
+                let now = Date::now();
+                if now - self.last_typing_sent_at < TYPING_THROTTLE_MS {
+                    return len_changed;
+                }
+                self.last_typing_sent_at = now;
+                let message = WebSocketMessage {
+                    data: Some(self.username.clone()),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Typing)
+                };
+                self.wss.send(message);
+                len_changed
+            }
+            Msg::MessagesScrolled => {
+                // Scrolling near the top asks for an older page before
+                // anything else below runs, so it's not gated on the
+                // bottom-only checks (read receipts, DMs) that follow.
+                let mut fetched_more = false;
+                if self.active_dm.is_none()
+                    && self.is_scrolled_near_top()
+                    && !self.history_loading.contains(&self.current_room)
+                    && !self.history_exhausted.contains(&self.current_room)
+                {
+                    if let Some(oldest_id) = self.current_room_messages().iter().find_map(|m| m.id.clone()) {
+                        self.history_loading.insert(self.current_room.clone());
+                        send_history_request(&self.wss, &self.current_room, Some(oldest_id));
+                        fetched_more = true;
+                    }
+                }
+
+                // Scrolling back down to the bottom on their own hides the
+                // "jump to latest" button the same way clicking it would.
+                let was_stuck = self.should_stick_to_bottom;
+                self.should_stick_to_bottom = self.is_scrolled_near_bottom();
+                if self.should_stick_to_bottom {
+                    self.mark_caught_up();
+                }
+                let fetched_more = fetched_more || was_stuck != self.should_stick_to_bottom;
+
+                // Tracking exactly which bubble is on screen would need
+                // per-message intersection observation; approximate it as
+                // "caught up with the newest message" once scrolled to the
+                // bottom, and report nothing while scrolled back through
+                // history. Read receipts aren't tracked for DMs.
+                if self.active_dm.is_some() || !self.should_stick_to_bottom {
+                    return fetched_more;
+                }
+                let Some(last_id) = self.current_room_messages().iter().rev().find_map(|m| m.id.clone()) else {
+                    return fetched_more;
+                };
+                if self.last_read_id_sent.as_deref() == Some(last_id.as_str()) {
+                    return fetched_more;
+                }
+                let now = Date::now();
+                if now - self.last_read_sent_at < READ_RECEIPT_THROTTLE_MS {
+                    return fetched_more;
+                }
+                self.last_read_sent_at = now;
+                self.last_read_id_sent = Some(last_id.clone());
+                let message = WebSocketMessage {
+                    data: Some(self.username.clone()),
+                    id: Some(last_id),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Read)
+                };
+                self.wss.send(message);
+                fetched_more
+            }
+            Msg::JumpToLatest => {
+                self.mark_caught_up();
+                self.should_stick_to_bottom = true;
+                if let Some(container) = self.messages_container.cast::<HtmlElement>() {
+                    container.set_scroll_top(container.scroll_height());
+                }
+                true
+            }
+            Msg::StartEdit(id) => {
+                let Some(existing) = self.messages.values().find_map(|room| room.iter().find(|m| m.id.as_deref() == Some(id.as_str()))) else {
+                    return false;
+                };
+                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                    input.set_value(&existing.message);
+                    input.focus().ok();
+                }
+                self.editing_id = Some(id);
+                true
+            }
+            Msg::CancelEdit => {
+                if self.editing_id.take().is_none() {
+                    return false;
+                }
+                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                    input.set_value("");
+                }
+                self.compose_len = 0;
+                true
+            }
+            Msg::DeleteMessage(id) => {
+                let message = WebSocketMessage {
+                    id: Some(id.clone()),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Delete)
+                };
+                self.wss.send(message);
+                if self.editing_id.as_deref() == Some(id.as_str()) {
+                    self.editing_id = None;
+                }
+                let changed = self.messages.values_mut().any(|room| delete_message(room, &id));
+                if changed {
+                    self.persist_messages();
+                }
+                changed
+            }
+            Msg::ToggleReaction(id, emoji) => {
+                let payload = ReactionPayload {
+                    emoji: emoji.clone(),
+                    user: self.username.clone(),
+                };
+                let data = match serde_json::to_string(&payload) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        log::warn!("failed to serialize outgoing reaction: {}", e);
+                        self.push_notice(format!("Couldn't send reaction: {}", e));
+                        return true;
+                    }
+                };
+                let message = WebSocketMessage {
+                    data: Some(data),
+                    id: Some(id.clone()),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::React)
+                };
+                self.wss.send(message);
+                let changed = self.messages.values_mut().any(|room| toggle_reaction(room, &id, &emoji, &self.username));
+                if changed {
+                    self.persist_messages();
+                }
+                changed
+            }
+            Msg::PruneTypingIndicators => {
+                let now = Date::now();
+                let typing_before = self.typing_users.len();
+                self.typing_users
+                    .retain(|_, last_seen| now - *last_seen < TYPING_EXPIRY_MS);
+                let notices_before = self.notices.len();
+                self.notices.retain(|n| now - n.created_at < NOTICE_EXPIRY_MS);
+                let mut expired = false;
+                for room in self.messages.values_mut() {
+                    expired |= prune_expired(room, now);
+                }
+                for dm in self.dm_messages.values_mut() {
+                    expired |= prune_expired(dm, now);
+                }
+                for thread in self.thread_replies.values_mut() {
+                    expired |= prune_expired(thread, now);
+                }
+                if expired {
+                    self.persist_messages();
+                }
+                let divider_cleared = match self.unread_divider_clear_at {
+                    Some(clear_at) if now >= clear_at => {
+                        self.unread_divider_after = None;
+                        self.unread_divider_clear_at = None;
+                        true
+                    }
+                    _ => false,
+                };
+                typing_before != self.typing_users.len() || notices_before != self.notices.len() || expired || divider_cleared
+            }
+            Msg::DismissNotice(id) => {
+                self.notices.retain(|n| n.id != id);
+                true
+            }
+            Msg::StartReply(id) => {
+                self.replying_to = Some(id);
+                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                    input.focus().ok();
+                }
+                true
+            }
+            Msg::CancelReply => {
+                self.replying_to = None;
+                true
+            }
+            Msg::ToggleEphemeral => {
+                self.ephemeral_ttl = match self.ephemeral_ttl {
+                    None => Some(30),
+                    Some(_) => None,
+                };
+                true
+            }
+            Msg::Logout => {
+                // Unlike an ordinary route change — where `destroy` leaves
+                // `wss` alone because the connection is meant to survive
+                // it — logging out should really end the session, so this
+                // closes it explicitly instead of relying on the `Rc` ever
+                // reaching zero references. Closing alone isn't enough,
+                // though: `wss_handle` still has this now-dead connection
+                // cached, and the next login commonly reuses the same
+                // (unpersisted, usually `None`) token, which would hand it
+                // straight back. Invalidate the cache too so that login
+                // forces a brand-new `WebsocketService`.
+                self.wss.close();
+                self.wss_handle.invalidate();
+                self.messages.clear();
+                self.users.clear();
+                self.user_order.clear();
+                self.dm_messages.clear();
+                self.thread_replies.clear();
+                clear_message_history();
+                crate::components::login::clear_saved_username();
+                if let Some(history) = ctx.link().history() {
+                    history.push(Route::Login);
+                }
+                // `_producer` and the `gloo_timers` intervals are dropped
+                // (and with them, cancelled) along with the rest of `self`
+                // once this component unmounts on the route switch above.
+                false
+            }
+            Msg::PinMessage(id) => {
+                let Some(snippet) = self.find_message(&id).map(reply_snippet_for) else {
+                    return false;
+                };
+                let payload = PinPayload { snippet: snippet.clone() };
+                let data = match serde_json::to_string(&payload) {
+                    Ok(data) => data,
+                    Err(e) => {
+                        log::warn!("failed to serialize outgoing pin: {}", e);
+                        self.push_notice(format!("Couldn't pin message: {}", e));
+                        return true;
+                    }
+                };
+                let message = WebSocketMessage {
+                    data: Some(data),
+                    id: Some(id.clone()),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Pin)
+                };
+                self.wss.send(message);
+                self.pending_pin_reverts.insert(id.clone(), false);
+                if let Some(m) = self.find_message_mut(&id) {
+                    m.pinned = true;
+                }
+                if !self.pinned.iter().any(|p| p.id == id) {
+                    self.pinned.push(PinnedMessage { id, snippet });
+                }
+                true
+            }
+            Msg::UnpinMessage(id) => {
+                let message = WebSocketMessage {
+                    id: Some(id.clone()),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Unpin)
+                };
+                self.wss.send(message);
+                self.pending_pin_reverts.insert(id.clone(), true);
+                if let Some(m) = self.find_message_mut(&id) {
+                    m.pinned = false;
+                }
+                self.pinned.retain(|p| p.id != id);
+                true
+            }
+            Msg::TogglePinnedStrip => {
+                self.pinned_strip_open = !self.pinned_strip_open;
+                true
+            }
+            Msg::ScrollToMessage(id) => {
+                scroll_to_message(&id);
+                false
+            }
+            Msg::MessageAcked(id) => {
+                match self.find_message_mut(&id) {
+                    Some(m) => {
+                        m.pending = false;
+                        m.status = MessageStatus::Delivered;
+                        true
                     }
+                    None => false,
+                }
+            }
+            Msg::MessageAckFailed(id, reason) => {
+                self.push_notice(format!("Message not delivered: {}", reason));
+                match self.find_message_mut(&id) {
+                    Some(m) => {
+                        m.pending = false;
+                        m.failed = true;
+                        true
+                    }
+                    None => true,
+                }
+            }
+            Msg::RetrySend(id) => {
+                let Some(m) = self.find_message_mut(&id) else {
+                    return false;
+                };
+                m.pending = true;
+                m.failed = false;
+                m.status = MessageStatus::Sending;
+                let message = WebSocketMessage {
+                    data: Some(m.message.clone()),
+                    timestamp: m.timestamp,
+                    id: Some(id.clone()),
+                    reply_to: m.reply_to.clone(),
+                    reply_snippet: m.reply_snippet.clone(),
+                    thread_root: m.thread_root.clone(),
+                    to: m.to.clone(),
+                    room: m.room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Message)
+                };
+                let ack_future = self.wss.send_with_ack(id.clone(), message);
+                let link = ctx.link().clone();
+                spawn_local(async move {
+                    match ack_future.await {
+                        Ok(()) => link.send_message(Msg::MessageAcked(id)),
+                        Err(reason) => link.send_message(Msg::MessageAckFailed(id, reason)),
+                    }
+                });
+                true
+            }
+            Msg::OpenThread(id) => {
+                if self.open_thread.as_deref() == Some(id.as_str()) {
+                    return false;
+                }
+                self.open_thread = Some(id);
+                true
+            }
+            Msg::CloseThread => self.open_thread.take().is_some(),
+            Msg::SubmitThreadMessage => {
+                let Some(root) = self.open_thread.clone() else { return false };
+                let Some(input) = self.thread_input.cast::<HtmlInputElement>() else { return false };
+                let text = input.value().trim().to_string();
+                if text.is_empty() {
+                    return true;
+                }
+                if text.chars().count() > MAX_MESSAGE_LENGTH {
+                    self.push_notice(format!("Message is too long (max {} characters)", MAX_MESSAGE_LENGTH));
+                    return true;
+                }
+                let id = generate_id();
+                let timestamp = Date::now();
+                let message = WebSocketMessage {
+                    data: Some(text.clone()),
+                    timestamp: Some(timestamp),
+                    id: Some(id.clone()),
+                    thread_root: Some(root.clone()),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Message)
+                };
+                let ack_future = self.wss.send_with_ack(id.clone(), message);
+                self.seen_message_ids.insert(id.clone());
+                let pending_message = MessageData {
+                    id: Some(id.clone()),
+                    from: self.username.clone(),
+                    message: text,
+                    attachments: Vec::new(),
+                    timestamp: Some(timestamp),
+                    pending: true,
+                    failed: false,
+                    status: MessageStatus::Sending,
+                    edited: false,
+                    deleted: false,
+                    pinned: false,
+                    reactions: std::collections::HashMap::new(),
+                    reply_to: None,
+                    reply_snippet: None,
+                    thread_root: Some(root.clone()),
+                    to: None,
+                    recipients: Vec::new(),
+                    expires_in: None,
+                    expires_at: None,
+                    room: self.current_room.clone(),
+                    image_url: None,
+                };
+                self.thread_replies.entry(root).or_default().push(pending_message);
+                let link = ctx.link().clone();
+                spawn_local(async move {
+                    match ack_future.await {
+                        Ok(()) => link.send_message(Msg::MessageAcked(id)),
+                        Err(reason) => link.send_message(Msg::MessageAckFailed(id, reason)),
+                    }
+                });
+                input.set_value("");
+                true
+            }
+            Msg::SwitchRoom(room) => {
+                if room == self.current_room {
+                    return false;
+                }
+                let leave = WebSocketMessage {
+                    data: Some(self.username.clone()),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Leave)
+                };
+                self.wss.send(leave);
+                self.current_room = room.clone();
+                self.unread_counts.remove(&room);
+                self.active_dm = None;
+                let join = WebSocketMessage {
+                    data: Some(self.username.clone()),
+                    room,
+                    ..WebSocketMessage::new(MsgTypes::Join)
+                };
+                self.wss.send(join);
+                send_register(&self.wss, &self.username, &self.current_room);
+                if self.history_loaded.insert(self.current_room.clone()) {
+                    send_history_request(&self.wss, &self.current_room, None);
+                    self.history_loading.insert(self.current_room.clone());
+                }
+                self.should_stick_to_bottom = true;
+                self.missed_while_scrolled = 0;
+                // Switching to a different room's message list makes the
+                // old divider boundary meaningless — clear it outright
+                // rather than letting it fade, since the list it pointed
+                // into isn't even the one on screen anymore.
+                self.unread_divider_after = None;
+                self.unread_divider_clear_at = None;
+                true
+            }
+            Msg::OpenDm(peer) => {
+                if !self.open_dm_tabs.contains(&peer) {
+                    self.open_dm_tabs.push(peer.clone());
+                }
+                self.dm_unread.remove(&peer);
+                self.active_dm = Some(peer);
+                self.should_stick_to_bottom = true;
+                self.missed_while_scrolled = 0;
+                self.unread_divider_after = None;
+                self.unread_divider_clear_at = None;
+                true
+            }
+            Msg::CloseDm(peer) => {
+                self.open_dm_tabs.retain(|p| p != &peer);
+                self.dm_unread.remove(&peer);
+                if self.active_dm.as_deref() == Some(peer.as_str()) {
+                    self.active_dm = None;
+                    self.should_stick_to_bottom = true;
+                    self.missed_while_scrolled = 0;
+                    self.unread_divider_after = None;
+                    self.unread_divider_clear_at = None;
+                }
+                true
+            }
+            Msg::ShowRoom => {
+                self.active_dm = None;
+                self.should_stick_to_bottom = true;
+                self.missed_while_scrolled = 0;
+                self.unread_divider_after = None;
+                self.unread_divider_clear_at = None;
+                true
+            }
+            Msg::VisibilityChanged => {
+                if !document_hidden() {
+                    self.unread = 0;
+                    set_unread_title(0);
+                    self.mentions_count = 0;
+                    return true;
+                }
+                false
+            }
+            Msg::UserActivity => {
+                self.last_activity_at = Date::now();
+                if self.self_presence == UserStatus::Away {
+                    self.send_presence(UserStatus::Online);
+                }
+                false
+            }
+            Msg::PresenceTick => {
+                let now = Date::now();
+                if self.self_presence != UserStatus::Away && now - self.last_activity_at >= IDLE_AWAY_MS {
+                    self.send_presence(UserStatus::Away);
+                }
+                let expired: Vec<String> = self
+                    .offline_since
+                    .iter()
+                    .filter(|(_, since)| now - **since >= OFFLINE_RETENTION_MS)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                if expired.is_empty() {
+                    return false;
+                }
+                for name in &expired {
+                    self.offline_since.remove(name);
+                    self.users.remove(name);
+                }
+                self.user_order.retain(|name| !expired.contains(name));
+                true
+            }
+            Msg::ToggleSound => {
+                self.sound_enabled = !self.sound_enabled;
+                save_sound_enabled(self.sound_enabled);
+                true
+            }
+            Msg::ToggleTheme => {
+                self.theme = self.theme.toggled();
+                save_theme(self.theme);
+                true
+            }
+            Msg::RetryConnection => {
+                self.wss.retry();
+                false
+            }
+            Msg::AttachImage => {
+                let Some(input) = self.image_input.cast::<HtmlInputElement>() else { return false };
+                let Some(files) = input.files() else { return false };
+                let Some(file) = files.get(0) else { return false };
+                if file.size() > MAX_IMAGE_BYTES {
+                    self.push_notice(format!("Image is too large to send (max {}KB).", (MAX_IMAGE_BYTES / 1_000.0) as u32));
                     input.set_value("");
+                    return true;
+                }
+                let reader = match web_sys::FileReader::new() {
+                    Ok(reader) => reader,
+                    Err(e) => {
+                        log::warn!("failed to create a FileReader for the attached image: {:?}", e);
+                        return false;
+                    }
+                };
+                let link = ctx.link().clone();
+                let onload_reader = reader.clone();
+                let onload = Closure::<dyn FnMut()>::new(move || match onload_reader.result() {
+                    Ok(result) => match result.as_string() {
+                        Some(data_url) => link.send_message(Msg::ImageDataReady(data_url)),
+                        None => log::warn!("FileReader result for an attached image wasn't a string"),
+                    },
+                    Err(e) => log::warn!("failed to read the attached image: {:?}", e),
+                });
+                reader.set_onload(Some(onload.as_ref().unchecked_ref()));
+                onload.forget();
+                if let Err(e) = reader.read_as_data_url(&file) {
+                    log::warn!("failed to start reading the attached image: {:?}", e);
+                }
+                input.set_value("");
+                false
+            }
+            Msg::ImageDataReady(data_url) => {
+                let Some((_, base64_payload)) = data_url.split_once(',') else {
+                    log::warn!("image data URL had no base64 payload");
+                    return false;
+                };
+                let message = WebSocketMessage {
+                    data: Some(base64_payload.to_string()),
+                    to: self.active_dm.clone(),
+                    room: self.current_room.clone(),
+                    ..WebSocketMessage::new(MsgTypes::Image)
                 };
+                self.wss.send(message);
                 false
             }
+            Msg::ToggleEmojiPicker => {
+                self.emoji_picker_open = !self.emoji_picker_open;
+                self.emoji_picker_focus = 0;
+                true
+            }
+            Msg::CloseEmojiPicker => {
+                if !self.emoji_picker_open {
+                    return false;
+                }
+                self.emoji_picker_open = false;
+                true
+            }
+            Msg::InsertEmoji(emoji) => {
+                if let Some(input) = self.chat_input.cast::<HtmlInputElement>() {
+                    if let Err(e) = input.set_range_text(&emoji) {
+                        log::warn!("failed to insert emoji into the chat input: {:?}", e);
+                    }
+                    input.focus().ok();
+                }
+                remember_emoji(&mut self.recent_emojis, &emoji);
+                save_recent_emojis(&self.recent_emojis);
+                self.emoji_picker_open = false;
+                true
+            }
+            Msg::EmojiPickerKey(e) => {
+                let picked = match e.key().as_str() {
+                    "Escape" => {
+                        e.prevent_default();
+                        self.emoji_picker_open = false;
+                        return true;
+                    }
+                    "Enter" => EMOJI_GRID.get(self.emoji_picker_focus).map(|s| s.to_string()),
+                    "ArrowRight" => {
+                        self.emoji_picker_focus = (self.emoji_picker_focus + 1).min(EMOJI_GRID.len() - 1);
+                        None
+                    }
+                    "ArrowLeft" => {
+                        self.emoji_picker_focus = self.emoji_picker_focus.saturating_sub(1);
+                        None
+                    }
+                    "ArrowDown" => {
+                        self.emoji_picker_focus = (self.emoji_picker_focus + EMOJI_GRID_COLUMNS).min(EMOJI_GRID.len() - 1);
+                        None
+                    }
+                    "ArrowUp" => {
+                        self.emoji_picker_focus = self.emoji_picker_focus.saturating_sub(EMOJI_GRID_COLUMNS);
+                        None
+                    }
+                    _ => return false,
+                };
+                e.prevent_default();
+                match picked {
+                    Some(emoji) => ctx.link().send_message(Msg::InsertEmoji(emoji)),
+                    None => {}
+                }
+                true
+            }
+            Msg::ToggleSearch => {
+                self.search_open = !self.search_open;
+                self.search_query.clear();
+                self.search_match_index = None;
+                true
+            }
+            Msg::SearchQueryChanged(query) => {
+                self.search_query = query;
+                self.search_match_index = None;
+                true
+            }
+            Msg::SearchNext => {
+                let matches = self.search_match_ids();
+                if matches.is_empty() {
+                    self.search_match_index = None;
+                    return false;
+                }
+                let next = match self.search_match_index {
+                    Some(i) => (i + 1) % matches.len(),
+                    None => 0,
+                };
+                self.search_match_index = Some(next);
+                scroll_to_message(&matches[next]);
+                true
+            }
+        }
+    }
+
+    fn destroy(&mut self, _ctx: &Context<Self>) {
+        // `wss` is a shared `Rc` owned by `Main`, not by this component, so
+        // dropping it here doesn't close the socket — the connection is
+        // meant to survive route changes. Dropping `_producer` still
+        // disconnects this component's `EventBus` bridge so no stale
+        // callback can fire into it once it's gone.
+        if let Some(document) = window().and_then(|w| w.document()) {
+            let _ = document.remove_event_listener_with_callback(
+                "visibilitychange",
+                self._visibility_listener.as_ref().unchecked_ref(),
+            );
+            for event in ["pointermove", "keydown"] {
+                let _ = document.remove_event_listener_with_callback(
+                    event,
+                    self._activity_listener.as_ref().unchecked_ref(),
+                );
+            }
+        }
+        log::debug!("Chat destroyed, leaving shared websocket connection open");
+    }
+
+    fn rendered(&mut self, _ctx: &Context<Self>, _first_render: bool) {
+        if let Some((old_scroll_height, old_scroll_top)) = self.pending_scroll_restore.take() {
+            if let Some(container) = self.messages_container.cast::<HtmlElement>() {
+                let grew_by = container.scroll_height() as f64 - old_scroll_height;
+                container.set_scroll_top((old_scroll_top + grew_by) as i32);
+            }
+            return;
+        }
+        if self.should_stick_to_bottom {
+            if let Some(container) = self.messages_container.cast::<HtmlElement>() {
+                container.set_scroll_top(container.scroll_height());
+            }
         }
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let submit = ctx.link().callback(|_| Msg::SubmitMessage);
-    
+        let oninput = ctx.link().callback(|_: InputEvent| Msg::InputChanged);
+        let typing_names: Vec<&String> = self
+            .typing_users
+            .keys()
+            .filter(|name| *name != &self.username)
+            .collect();
+        let visible_messages = self.visible_messages();
+        let group_starts: Vec<bool> = {
+            let mut prev: Option<&MessageData> = None;
+            visible_messages
+                .iter()
+                .map(|m| {
+                    let starts = starts_new_group(prev, m);
+                    prev = Some(*m);
+                    starts
+                })
+                .collect()
+        };
+        let day_labels: Vec<Option<String>> = {
+            let mut prev_label: Option<String> = None;
+            visible_messages
+                .iter()
+                .map(|m| {
+                    let label = m.timestamp.map(day_divider_label)?;
+                    let changed = prev_label.as_deref() != Some(label.as_str());
+                    prev_label = Some(label.clone());
+                    changed.then_some(label)
+                })
+                .collect()
+        };
+        // Which join/leave notices (if any) should render immediately before
+        // each visible message, keyed by index — `system_before[len]` holds
+        // whatever's left over to show after the very last message. DMs have
+        // no room to narrate, so they never get any.
+        let room_system_events: &[SystemEvent] = if self.active_dm.is_none() {
+            self.system_events.get(&self.current_room).map(Vec::as_slice).unwrap_or(&[])
+        } else {
+            &[]
+        };
+        let mut system_before: Vec<Vec<&SystemEvent>> = vec![Vec::new(); visible_messages.len() + 1];
+        for event in room_system_events {
+            let slot = visible_messages
+                .iter()
+                .position(|m| m.timestamp.map(|ts| event.timestamp < ts).unwrap_or(false))
+                .unwrap_or(visible_messages.len());
+            system_before[slot].push(event);
+        }
+        let last_read_own_message_id: Option<&str> = visible_messages
+            .iter()
+            .rev()
+            .find(|m| {
+                m.from == self.username
+                    && m.id
+                        .as_deref()
+                        .is_some_and(|id| !readers_of(self.current_room_messages(), &self.read_up_to, id, &self.username).is_empty())
+            })
+            .and_then(|m| m.id.as_deref());
+        let replying_to_preview = self.replying_to.as_ref().and_then(|id| {
+            visible_messages
+                .iter()
+                .find(|m| m.id.as_deref() == Some(id.as_str()))
+                .map(|m| format!("{}: {}", m.from, m.message))
+        });
+        let is_editing = self.editing_id.is_some();
+        let is_connected = self.connection_state.is_open() && self.registered;
+        let (dot_color, status_text, dot_pulses) = match self.connection_state {
+            ConnectionState::Connecting => ("bg-yellow-400", "Connecting...".to_string(), true),
+            ConnectionState::Open if !self.registered => ("bg-yellow-400", "Registering...".to_string(), true),
+            ConnectionState::Open => ("bg-green-500", "Connected".to_string(), false),
+            ConnectionState::Reconnecting => (
+                "bg-yellow-400",
+                format!("Reconnecting (attempt {})...", self.reconnect_attempt),
+                true,
+            ),
+            ConnectionState::Closed => ("bg-red-500", "Disconnected".to_string(), false),
+            ConnectionState::Unauthorized => (
+                "bg-red-500",
+                "Authentication failed — check your access token".to_string(),
+                false,
+            ),
+            ConnectionState::Failed => ("bg-red-500", "Connection failed".to_string(), false),
+        };
+        let theme = self.theme.classes();
+
         html! {
+            <>
+            {
+                if let Some(minimum_version) = self.upgrade_required {
+                    html! {
+                        <div class="fixed inset-0 z-50 flex items-center justify-center bg-black bg-opacity-75">
+                            <div class="bg-white rounded-lg shadow-lg p-8 max-w-sm text-center">
+                                <div class="text-lg font-semibold mb-2">{"Update required"}</div>
+                                <div class="text-sm text-gray-600 mb-4">
+                                    {format!(
+                                        "This app is running protocol v{}, but the server now requires at least v{}. Refresh to pick up the update.",
+                                        PROTOCOL_VERSION, minimum_version,
+                                    )}
+                                </div>
+                                <button onclick={Callback::from(|_| { if let Some(w) = window() { let _ = w.location().reload(); } })} class="px-4 py-2 bg-blue-600 text-white rounded-lg hover:bg-blue-700">
+                                    {"Refresh now"}
+                                </button>
+                            </div>
+                        </div>
+                    }
+                } else {
+                    html! {}
+                }
+            }
             <div class="flex w-screen">
+                // Channels section
+                <div class={format!("flex-none w-40 h-screen text-white shadow-lg {}", theme.channels_bg)}>
+                    <div class="text-xl p-4 font-semibold">{"Channels"}</div>
+                    {
+                        for CHANNELS.iter().map(|channel| {
+                            let unread = self.unread_counts.get(*channel).copied().unwrap_or(0);
+                            let is_active = *channel == self.current_room;
+                            let onclick = ctx.link().callback(move |_| Msg::SwitchRoom(channel.to_string()));
+                            html! {
+                                <div {onclick} class={format!(
+                                    "flex justify-between items-center mx-2 mb-1 px-3 py-2 rounded-lg cursor-pointer {}",
+                                    if is_active { theme.channels_active } else { theme.channels_hover },
+                                )}>
+                                    <span class="text-sm">{format!("# {}", channel)}</span>
+                                    {
+                                        if unread > 0 {
+                                            html! { <span class="text-xs bg-red-500 rounded-full px-2 py-0.5">{unread}</span> }
+                                        } else {
+                                            html! {}
+                                        }
+                                    }
+                                </div>
+                            }
+                        })
+                    }
+                </div>
                 // Users section
-                <div class="flex-none w-56 h-screen bg-blue-900 text-white shadow-lg">
+                <div class={format!("flex-none w-56 h-screen text-white shadow-lg {}", theme.users_bg)}>
                     <div class="text-xl p-4 font-semibold">{"Users"}</div>
                     {
-                        for self.users.iter().map(|u| {
+                        for self.user_order.iter().filter_map(|name| self.users.get(name)).filter(|u| u.name != self.username).map(|u| {
+                            let name = u.name.clone();
+                            let onclick = ctx.link().callback(move |_| Msg::OpenDm(name.clone()));
+                            let has_unread_dm = self.dm_unread.get(&u.name).copied().unwrap_or(0) > 0;
+                            let is_offline = u.status == UserStatus::Offline;
+                            let onerror = avatar_fallback_onerror(u.name.clone());
                             html! {
-                                <div class="flex m-4 bg-blue-100 rounded-xl shadow-md p-3">
-                                    <img class="w-12 h-12 rounded-full" src={u.avatar.clone()} alt="avatar"/>
+                                <div {onclick} class={format!(
+                                    "flex m-4 rounded-xl shadow-md p-3 cursor-pointer {} {} {}",
+                                    theme.user_card_bg, theme.user_card_hover,
+                                    if is_offline { "opacity-50" } else { "" },
+                                )}>
+                                    <div class="relative">
+                                        <img class="w-12 h-12 rounded-full" src={u.avatar.clone()} alt="avatar" {onerror}/>
+                                        <span class={format!(
+                                            "absolute bottom-0 right-0 w-3 h-3 rounded-full border-2 {} {}",
+                                            theme.user_card_bg, u.status.dot_color(),
+                                        )}></span>
+                                        {
+                                            if has_unread_dm {
+                                                html! { <span class="absolute -top-1 -right-1 w-3 h-3 rounded-full bg-red-500 border-2 border-white"></span> }
+                                            } else {
+                                                html! {}
+                                            }
+                                        }
+                                    </div>
                                     <div class="flex-grow ml-4">
-                                        <div class="text-sm font-medium">{&u.name}</div>
-                                        <div class="text-xs text-blue-900">{"Hi there!"}</div>
+                                        <div class={format!("text-sm {} {}", if has_unread_dm { "font-bold" } else { "font-medium" }, u.color)}>{&u.name}</div>
+                                        <div class={format!("text-xs {}", theme.user_status_text)}>{u.status.label()}</div>
                                     </div>
                                 </div>
                             }
@@ -158,45 +4018,1117 @@ impl Component for Chat {
                     }
                 </div>
                 // Chat section
-                <div class="grow h-screen flex flex-col bg-blue-50">
-                    <div class="w-full h-16 border-b-2 border-blue-300 flex items-center pl-4 bg-blue-200">
-                        <div class="text-xl font-semibold text-gray-800">{"💬 Chat"}</div>
+                <div class={format!("grow h-screen flex flex-col relative {}", theme.main_bg)}>
+                {
+                    if self.connection_state == ConnectionState::Failed {
+                        let retry = ctx.link().callback(|_| Msg::RetryConnection);
+                        html! {
+                            <div class="flex-grow flex flex-col items-center justify-center text-center p-8">
+                                <div class={format!("text-lg font-semibold mb-2 {}", theme.header_title_text)}>{"Unable to connect"}</div>
+                                <div class={format!("text-sm mb-4 {}", theme.status_text)}>
+                                    {"Gave up trying to reconnect after repeated failures."}
+                                </div>
+                                <button onclick={retry} class="px-4 py-2 bg-blue-600 text-white rounded-lg hover:bg-blue-700">
+                                    {"Try again"}
+                                </button>
+                            </div>
+                        }
+                    } else { html! { <>
+                    <div class={format!("w-full h-16 border-b-2 flex items-center pl-4 {} {}", theme.header_border, theme.header_bg)}>
+                        <div class={format!("text-xl font-semibold {}", theme.header_title_text)}>{"💬 Chat"}</div>
+                        <div class="flex items-center ml-4">
+                            <span class={format!("w-2 h-2 rounded-full mr-2 {} {}", dot_color, if dot_pulses { "animate-pulse" } else { "" })}></span>
+                            <span class={format!("text-sm {}", theme.status_text)}>{status_text}</span>
+                        </div>
+                        {
+                            let queued = self.wss.queued_len();
+                            if queued > 0 {
+                                html! {
+                                    <span class="text-xs text-gray-400 ml-3">
+                                        {format!("{} message{} queued", queued, if queued == 1 { "" } else { "s" })}
+                                    </span>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            if let Some(metrics) = &self.metrics {
+                                let latency = metrics
+                                    .last_latency_ms
+                                    .map(|ms| format!("{} ms", ms))
+                                    .unwrap_or_else(|| "? ms".to_string());
+                                let total_msgs = metrics.messages_sent + metrics.messages_received;
+                                html! {
+                                    <span class="text-xs text-gray-400 ml-3">
+                                        {format!("{} · {} msgs", latency, total_msgs)}
+                                    </span>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            if self.mentions_count > 0 {
+                                html! {
+                                    <span class="text-xs bg-yellow-400 text-gray-900 font-medium rounded-full px-2 py-0.5 ml-3">
+                                        {format!("@{} for you", self.mentions_count)}
+                                    </span>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            let toggle_sound = ctx.link().callback(|_| Msg::ToggleSound);
+                            html! {
+                                <button
+                                    onclick={toggle_sound}
+                                    class="ml-3 text-gray-500 hover:text-gray-800"
+                                    title={if self.sound_enabled { "Mute message sound" } else { "Unmute message sound" }}
+                                >
+                                    {if self.sound_enabled { "🔊" } else { "🔇" }}
+                                </button>
+                            }
+                        }
+                        {
+                            let toggle_theme = ctx.link().callback(|_| Msg::ToggleTheme);
+                            html! {
+                                <button
+                                    onclick={toggle_theme}
+                                    class="ml-3 text-gray-500 hover:text-gray-800"
+                                    title={if self.theme == Theme::Dark { "Switch to light mode" } else { "Switch to dark mode" }}
+                                >
+                                    {if self.theme == Theme::Dark { "☀️" } else { "🌙" }}
+                                </button>
+                            }
+                        }
+                        {
+                            let toggle_search = ctx.link().callback(|_| Msg::ToggleSearch);
+                            html! {
+                                <button
+                                    onclick={toggle_search}
+                                    class="ml-3 text-gray-500 hover:text-gray-800"
+                                    title={if self.search_open { "Close search" } else { "Search messages" }}
+                                >
+                                    {"🔍"}
+                                </button>
+                            }
+                        }
+                        {
+                            if !self.pinned.is_empty() {
+                                let toggle_pinned = ctx.link().callback(|_| Msg::TogglePinnedStrip);
+                                html! {
+                                    <button
+                                        onclick={toggle_pinned}
+                                        class="ml-3 text-gray-500 hover:text-gray-800 text-sm"
+                                        title={if self.pinned_strip_open { "Hide pinned messages" } else { "Show pinned messages" }}
+                                    >
+                                        {format!("📌 {}", self.pinned.len())}
+                                    </button>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            let logout = ctx.link().callback(|_| Msg::Logout);
+                            html! {
+                                <button onclick={logout} class="ml-3 text-gray-500 hover:text-gray-800" title="Log out">
+                                    {"🚪"}
+                                </button>
+                            }
+                        }
                     </div>
-                    <div class="flex-grow overflow-auto">
+                    {
+                        if self.pinned_strip_open && !self.pinned.is_empty() {
+                            html! {
+                                <div class={format!("w-full flex flex-col border-b {} {}", theme.tabs_border, theme.tabs_bg)}>
+                                    {
+                                        for self.pinned.iter().map(|p| {
+                                            let jump_id = p.id.clone();
+                                            let onclick = ctx.link().callback(move |_| Msg::ScrollToMessage(jump_id.clone()));
+                                            let unpin_id = p.id.clone();
+                                            let onunpin = ctx.link().callback(move |e: MouseEvent| { e.stop_propagation(); Msg::UnpinMessage(unpin_id.clone()) });
+                                            html! {
+                                                <div class="flex items-center justify-between px-4 py-1 text-xs text-gray-600 hover:bg-black/5 cursor-pointer" {onclick}>
+                                                    <span class="truncate">{format!("📌 {}", p.snippet)}</span>
+                                                    <button onclick={onunpin} class="ml-3 text-gray-400 hover:text-gray-700">{"✕"}</button>
+                                                </div>
+                                            }
+                                        })
+                                    }
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    {
+                        if self.search_open {
+                            let search_input_ref = self.search_input.clone();
+                            let query_input = ctx.link().callback(move |_: InputEvent| {
+                                let value = search_input_ref.cast::<HtmlInputElement>().map(|i| i.value()).unwrap_or_default();
+                                Msg::SearchQueryChanged(value)
+                            });
+                            let onkeydown = ctx.link().batch_callback(|e: KeyboardEvent| {
+                                if e.key() == "Enter" {
+                                    e.prevent_default();
+                                    Some(Msg::SearchNext)
+                                } else {
+                                    None
+                                }
+                            });
+                            let total_matches = self.search_match_ids().len();
+                            let position = self.search_match_index.map(|i| i + 1).unwrap_or(0);
+                            html! {
+                                <div class={format!("w-full flex items-center px-4 py-2 border-b {} {}", theme.tabs_border, theme.tabs_bg)}>
+                                    <input
+                                        ref={self.search_input.clone()}
+                                        oninput={query_input}
+                                        {onkeydown}
+                                        value={self.search_query.clone()}
+                                        type="text"
+                                        placeholder="Search messages..."
+                                        class={format!("flex-grow py-1 px-3 {} rounded-full outline-none text-sm", theme.input_field_bg)}
+                                    />
+                                    <span class="ml-3 text-xs text-gray-500 whitespace-nowrap">
+                                        {
+                                            if self.search_query.is_empty() {
+                                                String::new()
+                                            } else if total_matches == 0 {
+                                                "No matches".to_string()
+                                            } else {
+                                                format!("{}/{}", position.max(1), total_matches)
+                                            }
+                                        }
+                                    </span>
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    {
+                        if !self.open_dm_tabs.is_empty() {
+                            let room_click = ctx.link().callback(|_| Msg::ShowRoom);
+                            html! {
+                                <div class={format!("w-full flex items-center border-b {} {} px-2", theme.tabs_border, theme.tabs_bg)}>
+                                    <div onclick={room_click} class={format!(
+                                        "px-3 py-2 text-sm cursor-pointer {}",
+                                        if self.active_dm.is_none() { "font-semibold border-b-2 border-blue-600" } else { "text-gray-600" },
+                                    )}>
+                                        {format!("# {}", self.current_room)}
+                                    </div>
+                                    {
+                                        for self.open_dm_tabs.iter().map(|peer| {
+                                            let unread = self.dm_unread.get(peer).copied().unwrap_or(0);
+                                            let is_active = self.active_dm.as_deref() == Some(peer.as_str());
+                                            let peer_open = peer.clone();
+                                            let onclick = ctx.link().callback(move |_| Msg::OpenDm(peer_open.clone()));
+                                            let peer_close = peer.clone();
+                                            let onclose = ctx.link().callback(move |e: MouseEvent| { e.stop_propagation(); Msg::CloseDm(peer_close.clone()) });
+                                            html! {
+                                                <div {onclick} class={format!(
+                                                    "flex items-center px-3 py-2 text-sm cursor-pointer {}",
+                                                    if is_active { "font-semibold border-b-2 border-blue-600" } else { "text-gray-600" },
+                                                )}>
+                                                    <span>{format!("@{}", peer)}</span>
+                                                    {
+                                                        if unread > 0 {
+                                                            html! { <span class="ml-1 text-xs bg-red-500 text-white rounded-full px-1.5">{unread}</span> }
+                                                        } else {
+                                                            html! {}
+                                                        }
+                                                    }
+                                                    <button onclick={onclose} class="ml-2 text-gray-400 hover:text-gray-700">{"✕"}</button>
+                                                </div>
+                                            }
+                                        })
+                                    }
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    {
+                        if let Some(error) = &self.config_error {
+                            html! {
+                                <div class="w-full px-4 py-2 bg-red-100 text-red-800 text-sm">
+                                    {format!("Using default server: {}", error)}
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    <div
+                        ref={self.messages_container.clone()}
+                        class="flex-grow overflow-auto"
+                        onscroll={ctx.link().callback(|_: Event| Msg::MessagesScrolled)}
+                    >
                         {
-                            for self.messages.iter().map(|m| {
-                                let user = self.users.iter().find(|u| u.name == m.from).unwrap();
+                            if self.active_dm.is_none() && self.history_loading.contains(&self.current_room) {
+                                html! { <div class="text-center text-xs text-gray-400 my-2">{"Loading earlier messages…"}</div> }
+                            } else if self.active_dm.is_none() && self.history_exhausted.contains(&self.current_room) {
+                                html! { <div class="text-center text-xs text-gray-400 my-2">{"— beginning of conversation —"}</div> }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        {
+                            for visible_messages.iter().enumerate().map(|(i, m)| {
+                                let avatar = self.avatar_for(&m.from);
+                                let group_start = group_starts[i];
+                                let can_modify = m.from == self.username && !m.deleted;
+                                let edit_click = m.id.clone().map(|id| ctx.link().callback(move |_| Msg::StartEdit(id.clone())));
+                                let delete_click = m.id.clone().map(|id| ctx.link().callback(move |_| Msg::DeleteMessage(id.clone())));
+                                let reply_click = m.id.clone().map(|id| ctx.link().callback(move |_| Msg::StartReply(id.clone())));
+                                let pin_click = m.id.clone().map(|id| {
+                                    if m.pinned {
+                                        ctx.link().callback(move |_| Msg::UnpinMessage(id.clone()))
+                                    } else {
+                                        ctx.link().callback(move |_| Msg::PinMessage(id.clone()))
+                                    }
+                                });
+                                let message_to_copy = m.message.clone();
+                                let copy_click = Callback::from(move |e: MouseEvent| copy_message_to_clipboard(e, &message_to_copy));
+                                let quoted = m.reply_to.as_ref().and_then(|reply_id| {
+                                    visible_messages.iter().find(|other| other.id.as_deref() == Some(reply_id.as_str())).map(|other| format!("{}: {}", other.from, other.message))
+                                }).or_else(|| m.reply_snippet.clone());
+                                let is_search_match = self.search_open && self.is_search_match(m);
+                                let is_current_match = is_search_match
+                                    && self.search_match_index.is_some()
+                                    && self.search_match_ids().get(self.search_match_index.unwrap_or_default()) == m.id.as_ref();
+                                let day_label = day_labels[i].clone();
+                                let system_notices = system_before[i].clone();
+                                let mentions_me = m.from != self.username && mentions(&m.message, &self.username);
+                                let is_whisper = !m.recipients.is_empty();
                                 html! {
-                                    <div class={format!("flex items-end m-8 rounded-lg {}", if m.from == "You" { "bg-red-100" } else { "bg-green-100" })}>
-                                        <img class="w-8 h-8 rounded-full m-3" src={user.avatar.clone()} alt="avatar"/>
+                                <>
+                                {
+                                    for system_notices.iter().map(|event| html! {
+                                        <div class="text-center text-xs text-gray-400 my-2 italic">{&event.text}</div>
+                                    })
+                                }
+                                {
+                                    if let Some(label) = day_label {
+                                        html! { <div class="text-center text-xs text-gray-400 my-4">{label}</div> }
+                                    } else {
+                                        html! {}
+                                    }
+                                }
+                                    <div id={m.id.clone().map(|id| format!("msg-{}", id))} class={format!(
+                                        "group flex items-end {} rounded-lg {} {} {} {} {} {}",
+                                        if group_start { "m-8" } else { "mx-8 mb-1" },
+                                        if m.from == "You" { "bg-red-100" } else { "bg-green-100" },
+                                        if m.pending { "opacity-50" } else { "" },
+                                        if is_current_match { "ring-2 ring-yellow-400" } else { "" },
+                                        if mentions_me { "border-l-4 border-yellow-500" } else { "" },
+                                        if is_whisper { "border-2 border-dashed border-purple-400" } else { "" },
+                                        if m.pinned { "border-l-4 border-amber-500" } else { "" },
+                                    )}>
+                                        {
+                                            if group_start {
+                                                let onerror = avatar_fallback_onerror(m.from.clone());
+                                                html! { <img class="w-8 h-8 rounded-full m-3" src={avatar} alt="avatar" {onerror}/> }
+                                            } else {
+                                                html! { <div class="w-8 h-8 m-3 shrink-0"/> }
+                                            }
+                                        }
                                         <div class="flex flex-col p-3">
-                                            <div class="text-sm font-medium">{&m.from}</div>
-                                            <div class="text-xs text-gray-800 mt-1">
+                                            {
+                                                if group_start {
+                                                    html! {
+                                                        <div class={format!("text-sm font-medium {}", name_color(&m.from))}>
+                                                            { if is_search_match { highlight_matches(&m.from, &self.search_query) } else { html! { {&m.from} } } }
+                                                        </div>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if let (Some(quoted), Some(reply_id)) = (quoted, &m.reply_to) {
+                                                    let reply_id = reply_id.clone();
+                                                    let onclick = ctx.link().callback(move |_| Msg::ScrollToMessage(reply_id.clone()));
+                                                    html! {
+                                                        <div {onclick} class="text-xs text-gray-500 italic border-l-2 border-gray-300 pl-2 mt-1 cursor-pointer truncate max-w-xs">
+                                                            {quoted}
+                                                        </div>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            <div class={if m.deleted { "text-xs text-gray-400 italic mt-1" } else { "text-xs text-gray-800 mt-1" }}>
                                                 {
-                                                    if m.message.ends_with(".gif") {
+                                                    if m.deleted {
+                                                        render_markdown(&m.message)
+                                                    } else if let Some(image_url) = &m.image_url {
+                                                        html! { <img class="mt-1 max-w-xs rounded" src={image_url.clone()} /> }
+                                                    } else if !m.attachments.is_empty() {
+                                                        html! { <>{ for m.attachments.iter().map(render_attachment) }</> }
+                                                    } else if is_inline_image_url(&m.message) {
                                                         html! { <img class="mt-1" src={m.message.clone()} /> }
+                                                    } else if is_search_match {
+                                                        highlight_matches(&m.message, &self.search_query)
+                                                    } else {
+                                                        self.render_message_body(&m.message)
+                                                    }
+                                                }
+                                                {
+                                                    if m.edited {
+                                                        html! { <span class="text-gray-400 italic">{" (edited)"}</span> }
                                                     } else {
-                                                        html! { {&m.message} }
+                                                        html! {}
                                                     }
                                                 }
                                             </div>
+                                            {
+                                                if is_whisper {
+                                                    html! {
+                                                        <div class="text-xs text-purple-500 italic mt-1">
+                                                            {format!("only visible to {}", m.recipients.join(", "))}
+                                                        </div>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if m.expires_in.is_some() {
+                                                    html! {
+                                                        <div class="text-xs text-gray-400 italic mt-1">
+                                                            {"⏱ disappearing message"}
+                                                        </div>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if let Some(timestamp) = m.timestamp {
+                                                    let status_badge = if m.from == self.username && !m.failed && !m.pending {
+                                                        let effective = if m.id.as_deref().is_some_and(|id| {
+                                                            !readers_of(self.current_room_messages(), &self.read_up_to, id, &self.username).is_empty()
+                                                        }) {
+                                                            MessageStatus::Read
+                                                        } else {
+                                                            m.status
+                                                        };
+                                                        let (glyph, class) = status_glyph(effective);
+                                                        html! { <span class={class} title={format!("{:?}", effective)}>{glyph}</span> }
+                                                    } else {
+                                                        html! {}
+                                                    };
+                                                    html! {
+                                                        <div class="text-xs text-gray-400 mt-1 text-right flex items-center justify-end gap-1">
+                                                            {format_timestamp(timestamp)}
+                                                            {status_badge}
+                                                        </div>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if m.pending {
+                                                    html! { <div class="text-xs text-gray-400 italic">{"Sending..."}</div> }
+                                                } else if m.failed {
+                                                    let retry_id = m.id.clone();
+                                                    let retry = retry_id.map(|id| ctx.link().callback(move |_| Msg::RetrySend(id.clone())));
+                                                    html! {
+                                                        <div class="text-xs text-red-500 italic flex items-center gap-1">
+                                                            <span>{"Failed to send"}</span>
+                                                            { if let Some(retry) = retry {
+                                                                html! { <button onclick={retry} class="underline">{"retry"}</button> }
+                                                            } else {
+                                                                html! {}
+                                                            } }
+                                                        </div>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if m.from == self.username && m.id.as_deref() == last_read_own_message_id {
+                                                    let readers = readers_of(self.current_room_messages(), &self.read_up_to, m.id.as_deref().unwrap_or_default(), &self.username);
+                                                    match seen_by_text(readers) {
+                                                        Some(text) => html! { <div class="text-xs text-gray-400 mt-1 text-right">{text}</div> },
+                                                        None => html! {},
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if m.deleted || m.id.is_none() {
+                                                    html! {}
+                                                } else {
+                                                    let id = m.id.clone().unwrap();
+                                                    html! {
+                                                        <div class="flex mt-1 gap-1">
+                                                            { for REACTION_EMOJIS.iter().map(|emoji| {
+                                                                let count = m.reactions.get(*emoji).map(|u| u.len()).unwrap_or(0);
+                                                                let reacted = m.reactions.get(*emoji).map(|u| u.iter().any(|u| u == &self.username)).unwrap_or(false);
+                                                                let id = id.clone();
+                                                                let emoji_owned = emoji.to_string();
+                                                                let onclick = ctx.link().callback(move |_| Msg::ToggleReaction(id.clone(), emoji_owned.clone()));
+                                                                html! {
+                                                                    <button {onclick} class={format!(
+                                                                        "text-xs rounded-full px-2 py-0.5 {}",
+                                                                        if reacted { "bg-blue-200" } else { "bg-gray-200" },
+                                                                    )}>
+                                                                        {emoji}
+                                                                        { if count > 0 { format!(" {}", count) } else { String::new() } }
+                                                                    </button>
+                                                                }
+                                                            }) }
+                                                        </div>
+                                                    }
+                                                }
+                                            }
+                                            {
+                                                let reply_count = m.id.as_deref().and_then(|id| self.thread_replies.get(id)).map(|t| t.len()).unwrap_or(0);
+                                                if reply_count > 0 {
+                                                    let thread_id = m.id.clone().unwrap();
+                                                    let onclick = ctx.link().callback(move |_| Msg::OpenThread(thread_id.clone()));
+                                                    html! {
+                                                        <button {onclick} class="text-xs text-blue-600 hover:underline mt-1 self-start">
+                                                            {format!("{} {}", reply_count, if reply_count == 1 { "reply" } else { "replies" })}
+                                                        </button>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
                                         </div>
+                                        <>
+                                            {
+                                                if !m.deleted {
+                                                    html! {
+                                                        <button onclick={copy_click} title="Copy message" class="hidden group-hover:inline text-xs text-gray-600 self-start mt-3 mr-2">
+                                                            {"Copy"}
+                                                        </button>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if !m.deleted {
+                                                    if let Some(onclick) = reply_click {
+                                                        html! {
+                                                            <button {onclick} class="hidden group-hover:inline text-xs text-gray-600 self-start mt-3 mr-2">
+                                                                {"Reply"}
+                                                            </button>
+                                                        }
+                                                    } else {
+                                                        html! {}
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if !m.deleted {
+                                                    if let Some(onclick) = pin_click {
+                                                        html! {
+                                                            <button {onclick} class="hidden group-hover:inline text-xs text-gray-600 self-start mt-3 mr-2">
+                                                                {if m.pinned { "Unpin" } else { "Pin" }}
+                                                            </button>
+                                                        }
+                                                    } else {
+                                                        html! {}
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                            {
+                                                if can_modify {
+                                                    html! {
+                                                        <>
+                                                            {
+                                                                if let Some(onclick) = edit_click {
+                                                                    html! {
+                                                                        <button {onclick} class="hidden group-hover:inline text-xs text-blue-600 self-start mt-3 mr-2">
+                                                                            {"Edit"}
+                                                                        </button>
+                                                                    }
+                                                                } else {
+                                                                    html! {}
+                                                                }
+                                                            }
+                                                            {
+                                                                if let Some(onclick) = delete_click {
+                                                                    html! {
+                                                                        <button {onclick} class="hidden group-hover:inline text-xs text-red-600 self-start mt-3 mr-2">
+                                                                            {"🗑"}
+                                                                        </button>
+                                                                    }
+                                                                } else {
+                                                                    html! {}
+                                                                }
+                                                            }
+                                                        </>
+                                                    }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                        </>
+                                    </div>
+                                    {
+                                        if m.id.is_some() && m.id == self.unread_divider_after {
+                                            html! {
+                                                <div class="flex items-center mx-8 my-1">
+                                                    <div class="flex-grow border-t border-red-400"></div>
+                                                    <span class="px-2 text-xs text-red-500 font-medium">{"New"}</span>
+                                                    <div class="flex-grow border-t border-red-400"></div>
+                                                </div>
+                                            }
+                                        } else {
+                                            html! {}
+                                        }
+                                    }
+                                </>
+                                }
+                            })
+                        }
+                        {
+                            for system_before[visible_messages.len()].iter().map(|event| html! {
+                                <div class="text-center text-xs text-gray-400 my-2 italic">{&event.text}</div>
+                            })
+                        }
+                    </div>
+                    <div class="absolute top-4 right-4 z-50 flex flex-col gap-2 items-end">
+                        {
+                            for self.notices.iter().map(|n| {
+                                let id = n.id.clone();
+                                let ondismiss = ctx.link().callback(move |_| Msg::DismissNotice(id.clone()));
+                                html! {
+                                    <div class="max-w-xs px-4 py-2 rounded-lg shadow-lg bg-red-100 text-red-800 text-sm flex justify-between items-start gap-3">
+                                        <span>{&n.text}</span>
+                                        <button onclick={ondismiss} class="text-red-800 font-bold">{"✕"}</button>
                                     </div>
                                 }
                             })
                         }
                     </div>
-                    <div class="w-full h-16 flex px-4 items-center bg-white">
-                        <input ref={self.chat_input.clone()} type="text" placeholder="Type a message..." class="block w-full py-2 pl-4 mx-3 bg-gray-200 rounded-full outline-none focus:bg-white" name="message" required=true />
-                        <button onclick={submit} class="ml-3 p-2 bg-blue-600 w-12 h-12 rounded-full flex justify-center items-center text-white">
-                            <svg viewBox="0 0 24 24" xmlns="http://www.w3.org/2000/svg" class="fill-current">
-                                <path d="M0 0h24v24H0z" fill="none"></path>
-                                <path d="M2.01 21L23 12 2.01 3 2 10l15 2-15 2z"></path>
-                            </svg>
+                    {
+                        if !self.should_stick_to_bottom {
+                            let onclick = ctx.link().callback(|_| Msg::JumpToLatest);
+                            html! {
+                                <button {onclick} class="absolute bottom-24 right-8 px-4 py-2 rounded-full shadow-lg bg-blue-600 text-white text-xs font-medium hover:bg-blue-700">
+                                    {
+                                        if self.missed_while_scrolled > 0 {
+                                            format!("↓ {} new messages", self.missed_while_scrolled)
+                                        } else {
+                                            "↓ New messages".to_string()
+                                        }
+                                    }
+                                </button>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    {
+                        let names: Vec<&str> = typing_names.iter().map(|n| n.as_str()).collect();
+                        match typing_indicator_text(&names) {
+                            None => html! {},
+                            Some(text) => html! {
+                                <div class="px-4 py-1 text-xs text-gray-500 italic animate-pulse">
+                                    {text}
+                                </div>
+                            },
+                        }
+                    }
+                    {
+                        if is_editing {
+                            let cancel = ctx.link().callback(|_| Msg::CancelEdit);
+                            html! {
+                                <div class="px-4 py-1 flex items-center justify-between bg-gray-100 text-xs text-gray-600">
+                                    <span class="truncate">{"Editing message"}</span>
+                                    <button onclick={cancel} class="ml-4 text-gray-600 font-bold">{"✕"}</button>
+                                </div>
+                            }
+                        } else if let Some(preview) = replying_to_preview {
+                            let cancel = ctx.link().callback(|_| Msg::CancelReply);
+                            html! {
+                                <div class="px-4 py-1 flex items-center justify-between bg-gray-100 text-xs text-gray-600">
+                                    <span class="truncate">{format!("Replying to {}", preview)}</span>
+                                    <button onclick={cancel} class="ml-4 text-gray-600 font-bold">{"✕"}</button>
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    <div class={format!("relative w-full h-16 flex px-4 items-center {}", theme.input_bar_bg)}>
+                        {
+                            if self.emoji_picker_open {
+                                let close = ctx.link().callback(|_| Msg::CloseEmojiPicker);
+                                let onkeydown = ctx.link().callback(Msg::EmojiPickerKey);
+                                html! {
+                                    <>
+                                        <div onclick={close} class="fixed inset-0 z-40"></div>
+                                        <div onkeydown={onkeydown} tabindex="0" class={format!("absolute bottom-full left-4 mb-2 z-50 p-2 rounded shadow-lg grid grid-cols-8 gap-1 {}", theme.input_field_bg)}>
+                                            {
+                                                if self.recent_emojis.is_empty() {
+                                                    html! {}
+                                                } else {
+                                                    html! {
+                                                        <>
+                                                        { for self.recent_emojis.iter().map(|emoji| {
+                                                            let emoji_owned = emoji.clone();
+                                                            let onclick = ctx.link().callback(move |_| Msg::InsertEmoji(emoji_owned.clone()));
+                                                            html! { <button {onclick} class="text-lg hover:bg-gray-300 hover:bg-opacity-30 rounded">{emoji}</button> }
+                                                        }) }
+                                                        <div class="col-span-8 border-t border-gray-400 border-opacity-30 my-1"></div>
+                                                        </>
+                                                    }
+                                                }
+                                            }
+                                            { for EMOJI_GRID.iter().enumerate().map(|(i, emoji)| {
+                                                let emoji_owned = emoji.to_string();
+                                                let onclick = ctx.link().callback(move |_| Msg::InsertEmoji(emoji_owned.clone()));
+                                                let focused = i == self.emoji_picker_focus;
+                                                html! {
+                                                    <button {onclick} class={format!("text-lg rounded {}", if focused { "bg-blue-300 bg-opacity-50" } else { "hover:bg-gray-300 hover:bg-opacity-30" })}>
+                                                        {emoji}
+                                                    </button>
+                                                }
+                                            }) }
+                                        </div>
+                                    </>
+                                }
+                            } else {
+                                html! {}
+                            }
+                        }
+                        <input ref={self.image_input.clone()} onchange={ctx.link().callback(|_| Msg::AttachImage)} type="file" accept="image/*" class="hidden" />
+                        <button onclick={let image_input = self.image_input.clone(); Callback::from(move |_| { if let Some(input) = image_input.cast::<HtmlInputElement>() { input.click(); } })} title="Attach an image" class="p-2 text-gray-500 hover:text-gray-800">
+                            {"📎"}
+                        </button>
+                        <button onclick={ctx.link().callback(|_| Msg::ToggleEmojiPicker)} title="Insert an emoji" class="p-2 text-gray-500 hover:text-gray-800">
+                            {"🙂"}
                         </button>
+                        <button
+                            onclick={ctx.link().callback(|_| Msg::ToggleEphemeral)}
+                            title={if self.ephemeral_ttl.is_some() { "Sending as a 30s disappearing message" } else { "Send as a disappearing message" }}
+                            class={format!("p-2 text-xs font-medium rounded-full {}", if self.ephemeral_ttl.is_some() { "text-blue-600" } else { "text-gray-500 hover:text-gray-800" })}
+                        >
+                            { if self.ephemeral_ttl.is_some() { "⏱ 30s" } else { "⏱" } }
+                        </button>
+                        <input
+                            ref={self.chat_input.clone()}
+                            {oninput}
+                            onkeydown={ctx.link().batch_callback(|e: KeyboardEvent| {
+                                match e.key().as_str() {
+                                    "Escape" => Some(Msg::CancelEdit),
+                                    // Shift+Enter would insert a newline if this were a
+                                    // multi-line input — it isn't, so there's nothing to
+                                    // do but let Enter through to submit either way.
+                                    "Enter" if !e.shift_key() => {
+                                        e.prevent_default();
+                                        Some(Msg::SubmitMessage)
+                                    }
+                                    _ => None,
+                                }
+                            })}
+                            type="text"
+                            placeholder={if is_editing { "Edit message..." } else { "Type a message..." }}
+                            class={format!("block w-full py-2 pl-4 mx-3 {} rounded-full outline-none", theme.input_field_bg)}
+                            name="message"
+                            required=true
+                        />
+                        {
+                            if is_editing {
+                                html! {
+                                    <button onclick={submit} disabled={!is_connected} class="ml-3 px-4 h-12 bg-blue-600 rounded-full text-white text-sm font-medium disabled:opacity-50">
+                                        {"Save"}
+                                    </button>
+                                }
+                            } else {
+                                html! {
+                                    <button onclick={submit} disabled={!is_connected} class="ml-3 p-2 bg-blue-600 w-12 h-12 rounded-full flex justify-center items-center text-white disabled:opacity-50">
+                                        <svg viewBox="0 0 24 24" xmlns="http://www.w3.org/2000/svg" class="fill-current">
+                                            <path d="M0 0h24v24H0z" fill="none"></path>
+                                            <path d="M2.01 21L23 12 2.01 3 2 10l15 2-15 2z"></path>
+                                        </svg>
+                                    </button>
+                                }
+                            }
+                        }
                     </div>
+                    {
+                        if self.compose_len > 0 {
+                            let over_limit = self.compose_len > MAX_MESSAGE_LENGTH;
+                            html! {
+                                <div class={format!(
+                                    "px-4 pb-1 text-right text-xs {}",
+                                    if over_limit { "text-red-500" } else { "text-gray-400" },
+                                )}>
+                                    {format!("{}/{}", self.compose_len, MAX_MESSAGE_LENGTH)}
+                                </div>
+                            }
+                        } else {
+                            html! {}
+                        }
+                    }
+                    </> } }
+                }
                 </div>
+                {
+                    if let Some(root_id) = &self.open_thread {
+                        let replies = self.thread_replies.get(root_id.as_str()).map(Vec::as_slice).unwrap_or(&[]);
+                        let root_preview = self.find_message(root_id).map(|m| format!("{}: {}", m.from, m.message));
+                        let close = ctx.link().callback(|_| Msg::CloseThread);
+                        let submit_thread = ctx.link().callback(|_| Msg::SubmitThreadMessage);
+                        html! {
+                            <div class={format!("flex-none w-80 h-screen flex flex-col border-l {} {}", theme.tabs_border, theme.main_bg)}>
+                                <div class={format!("h-16 border-b-2 flex items-center justify-between px-4 {} {}", theme.header_border, theme.header_bg)}>
+                                    <div class={format!("text-sm font-semibold {}", theme.header_title_text)}>{"Thread"}</div>
+                                    <button onclick={close} class="text-gray-500 hover:text-gray-800">{"✕"}</button>
+                                </div>
+                                {
+                                    if let Some(preview) = root_preview {
+                                        html! { <div class="px-4 py-2 text-xs text-gray-500 italic border-b truncate">{preview}</div> }
+                                    } else {
+                                        html! {}
+                                    }
+                                }
+                                <div class="flex-grow overflow-auto p-3">
+                                    { for replies.iter().map(|m| html! {
+                                        <div class="mb-3">
+                                            <div class="text-xs font-medium">{&m.from}</div>
+                                            <div class="text-xs text-gray-800">{self.render_message_body(&m.message)}</div>
+                                            {
+                                                if m.pending {
+                                                    html! { <div class="text-xs text-gray-400 italic">{"Sending..."}</div> }
+                                                } else {
+                                                    html! {}
+                                                }
+                                            }
+                                        </div>
+                                    }) }
+                                </div>
+                                <div class={format!("h-16 flex px-2 items-center {}", theme.input_bar_bg)}>
+                                    <input
+                                        ref={self.thread_input.clone()}
+                                        onkeydown={ctx.link().batch_callback(|e: KeyboardEvent| {
+                                            if e.key() == "Enter" && !e.shift_key() {
+                                                e.prevent_default();
+                                                Some(Msg::SubmitThreadMessage)
+                                            } else {
+                                                None
+                                            }
+                                        })}
+                                        type="text"
+                                        placeholder="Reply in thread..."
+                                        class={format!("block w-full py-2 pl-3 mx-1 {} rounded-full outline-none text-sm", theme.input_field_bg)}
+                                    />
+                                    <button onclick={submit_thread} class="px-3 h-10 bg-blue-600 rounded-full text-white text-xs font-medium">
+                                        {"Send"}
+                                    </button>
+                                </div>
+                            </div>
+                        }
+                    } else {
+                        html! {}
+                    }
+                }
             </div>
+            </>
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::{
+        dedupe_key, delete_message, initials, mentions, parse_ephemeral, parse_whisper, prune_expired,
+        push_bounded_messages, readers_of, seen_by_text, starts_new_group, status_glyph, typing_indicator_text,
+        Attachment, MessageData, MessageStatus, DEFAULT_MESSAGE_CAP, MESSAGE_GROUP_GAP_MS,
+    };
+
+    fn message(id: &str) -> MessageData {
+        MessageData {
+            id: Some(id.to_string()),
+            from: "bob".to_string(),
+            message: "hi".to_string(),
+            attachments: Vec::new(),
+            timestamp: None,
+            pending: false,
+            failed: false,
+            status: MessageStatus::Delivered,
+            edited: false,
+            deleted: false,
+            pinned: false,
+            reactions: std::collections::HashMap::new(),
+            reply_to: None,
+            reply_snippet: None,
+            thread_root: None,
+            to: None,
+            recipients: Vec::new(),
+            expires_in: None,
+            expires_at: None,
+            room: "general".to_string(),
+            image_url: None,
+        }
+    }
+
+    #[test]
+    fn deleting_unknown_id_is_a_no_op() {
+        let mut messages = vec![message("1"), message("2")];
+        assert!(!delete_message(&mut messages, "does-not-exist"));
+        assert_eq!(messages[0].message, "hi");
+        assert_eq!(messages[1].message, "hi");
+    }
+
+    #[test]
+    fn deleting_known_id_replaces_it_with_a_tombstone() {
+        let mut messages = vec![message("1")];
+        assert!(delete_message(&mut messages, "1"));
+        assert!(messages[0].deleted);
+        assert_eq!(messages[0].message, "This message was deleted");
+    }
+
+    #[test]
+    fn pushing_past_the_cap_drains_the_oldest_messages() {
+        let mut messages = Vec::new();
+        let mut all_evicted = Vec::new();
+        for i in 0..600 {
+            all_evicted.extend(push_bounded_messages(&mut messages, DEFAULT_MESSAGE_CAP, message(&i.to_string())));
+        }
+        assert_eq!(messages.len(), DEFAULT_MESSAGE_CAP);
+        assert_eq!(messages.first().unwrap().id.as_deref(), Some("100"));
+        assert_eq!(messages.last().unwrap().id.as_deref(), Some("599"));
+        assert_eq!(all_evicted.len(), 100);
+        assert_eq!(all_evicted.first().unwrap().id.as_deref(), Some("0"));
+        assert_eq!(all_evicted.last().unwrap().id.as_deref(), Some("99"));
+    }
+
+    #[test]
+    fn typing_indicator_handles_one_two_and_many_typists() {
+        assert_eq!(typing_indicator_text(&[]), None);
+        assert_eq!(typing_indicator_text(&["alice"]), Some("alice is typing...".to_string()));
+        assert_eq!(typing_indicator_text(&["alice", "bob"]), Some("alice and bob are typing...".to_string()));
+        assert_eq!(
+            typing_indicator_text(&["alice", "bob", "carol"]),
+            Some("alice and 2 others are typing...".to_string())
+        );
+    }
+
+    #[test]
+    fn mentions_matches_case_insensitively_at_a_word_boundary() {
+        assert!(mentions("hey @bob check this out", "bob"));
+        assert!(mentions("hey @Bob,", "bob"));
+        assert!(mentions("@BOB!!", "bob"));
+        assert!(!mentions("bob@example.com sent this", "bob"));
+        assert!(!mentions("hey @bobby", "bob"));
+    }
+
+    #[test]
+    fn message_without_an_attachments_field_still_deserializes_as_text_only() {
+        let legacy = r#"{"from":"bob","message":"hi"}"#;
+        let parsed: MessageData = serde_json::from_str(legacy).unwrap();
+        assert_eq!(parsed.message, "hi");
+        assert!(parsed.attachments.is_empty());
+    }
+
+    #[test]
+    fn attachment_round_trips_through_json() {
+        let image = Attachment::Image { url: "https://example.com/cat.png".to_string(), alt: "a cat".to_string() };
+        let json = serde_json::to_string(&image).unwrap();
+        let parsed: Attachment = serde_json::from_str(&json).unwrap();
+        match parsed {
+            Attachment::Image { url, alt } => {
+                assert_eq!(url, "https://example.com/cat.png");
+                assert_eq!(alt, "a cat");
+            }
+            _ => panic!("expected an Image attachment"),
         }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn parse_whisper_extracts_leading_at_mentions_as_recipients() {
+        assert_eq!(
+            parse_whisper("/whisper @alice @bob meet me at noon"),
+            Some((vec!["alice".to_string(), "bob".to_string()], "meet me at noon".to_string())),
+        );
+    }
+
+    #[test]
+    fn parse_whisper_rejects_commands_with_no_recipients_or_no_message() {
+        assert_eq!(parse_whisper("/whisper hello"), None);
+        assert_eq!(parse_whisper("/whisper @alice"), None);
+        assert_eq!(parse_whisper("not a whisper at all"), None);
+    }
+
+    #[test]
+    fn parse_ephemeral_extracts_leading_seconds_as_ttl() {
+        assert_eq!(parse_ephemeral("/tmp 30 self-destructing text"), Some((30, "self-destructing text".to_string())));
+    }
+
+    #[test]
+    fn parse_ephemeral_rejects_commands_with_no_number_or_no_message() {
+        assert_eq!(parse_ephemeral("/tmp 30"), None);
+        assert_eq!(parse_ephemeral("/tmp soon hello"), None);
+        assert_eq!(parse_ephemeral("not a tmp command"), None);
+    }
+
+    #[test]
+    fn parse_command_extracts_a_me_action() {
+        assert_eq!(parse_command("/me waves hello"), Command::Me("waves hello".to_string()));
+        assert_eq!(parse_command("/me"), Command::Unknown("/me".to_string()));
+        assert_eq!(parse_command("/me   "), Command::Unknown("/me   ".to_string()));
+    }
+
+    #[test]
+    fn parse_command_recognizes_shrug_with_or_without_leading_text() {
+        assert_eq!(parse_command("/shrug"), Command::Shrug(String::new()));
+        assert_eq!(parse_command("/shrug whatever"), Command::Shrug("whatever".to_string()));
+    }
+
+    #[test]
+    fn parse_command_recognizes_clear() {
+        assert_eq!(parse_command("/clear"), Command::Clear);
+        // Only the bare command clears; trailing text isn't a recognized variant of it.
+        assert_eq!(parse_command("/clear now"), Command::Unknown("/clear now".to_string()));
+    }
+
+    #[test]
+    fn parse_command_falls_through_for_ordinary_text_and_other_commands() {
+        assert_eq!(parse_command("hello there"), Command::None);
+        assert_eq!(parse_command("/whisper @alice hi"), Command::None);
+        assert_eq!(parse_command("/tmp 30 hi"), Command::None);
+        assert_eq!(parse_command("/nonexistent"), Command::Unknown("/nonexistent".to_string()));
+    }
+
+    #[test]
+    fn prune_expired_removes_only_messages_past_their_deadline() {
+        let mut messages = vec![
+            MessageData { expires_at: Some(1_000.0), ..message("1") },
+            MessageData { expires_at: Some(2_000.0), ..message("2") },
+            MessageData { expires_at: None, ..message("3") },
+        ];
+        assert!(prune_expired(&mut messages, 1_500.0));
+        assert_eq!(messages.iter().map(|m| m.id.clone().unwrap()).collect::<Vec<_>>(), vec!["2", "3"]);
+    }
+
+    #[test]
+    fn prune_expired_is_a_no_op_when_nothing_has_expired_yet() {
+        let mut messages = vec![MessageData { expires_at: Some(2_000.0), ..message("1") }];
+        assert!(!prune_expired(&mut messages, 1_500.0));
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn initials_takes_one_letter_per_word_or_two_from_a_single_word() {
+        assert_eq!(initials("Dhafin Fakhri"), "DF");
+        assert_eq!(initials("dhafin"), "DH");
+        assert_eq!(initials(""), "");
+    }
+
+    #[test]
+    fn readers_of_excludes_users_who_have_not_reached_the_target_message() {
+        let messages = vec![message("1"), message("2"), message("3")];
+        let mut read_up_to = std::collections::HashMap::new();
+        read_up_to.insert("alice".to_string(), "3".to_string());
+        read_up_to.insert("carol".to_string(), "1".to_string());
+        let readers = readers_of(&messages, &read_up_to, "2", "bob");
+        assert_eq!(readers, vec!["alice"]);
+    }
+
+    #[test]
+    fn readers_of_ignores_a_read_receipt_for_an_unreceived_message() {
+        let messages = vec![message("1")];
+        let mut read_up_to = std::collections::HashMap::new();
+        read_up_to.insert("alice".to_string(), "not-yet-received".to_string());
+        assert!(readers_of(&messages, &read_up_to, "1", "bob").is_empty());
+    }
+
+    #[test]
+    fn seen_by_text_collapses_past_two_readers() {
+        assert_eq!(seen_by_text(vec![]), None);
+        assert_eq!(seen_by_text(vec!["alice"]), Some("Seen by alice".to_string()));
+        assert_eq!(seen_by_text(vec!["bob", "alice"]), Some("Seen by alice and bob".to_string()));
+        assert_eq!(
+            seen_by_text(vec!["carol", "alice", "bob"]),
+            Some("Seen by alice and 2 others".to_string())
+        );
+    }
+
+    #[test]
+    fn starts_new_group_for_the_first_message() {
+        assert!(starts_new_group(None, &message("1")));
+    }
+
+    #[test]
+    fn starts_new_group_on_a_sender_change() {
+        let prev = MessageData { from: "alice".to_string(), ..message("1") };
+        let next = MessageData { from: "bob".to_string(), ..message("2") };
+        assert!(starts_new_group(Some(&prev), &next));
+    }
+
+    #[test]
+    fn same_sender_stacks_without_a_long_gap() {
+        let prev = MessageData { timestamp: Some(1_000.0), ..message("1") };
+        let next = MessageData { timestamp: Some(1_000.0 + MESSAGE_GROUP_GAP_MS), ..message("2") };
+        assert!(!starts_new_group(Some(&prev), &next));
+    }
+
+    #[test]
+    fn same_sender_restarts_the_group_after_a_long_gap() {
+        let prev = MessageData { timestamp: Some(1_000.0), ..message("1") };
+        let next = MessageData { timestamp: Some(1_000.0 + MESSAGE_GROUP_GAP_MS + 1.0), ..message("2") };
+        assert!(starts_new_group(Some(&prev), &next));
+    }
+
+    #[test]
+    fn dedupe_key_uses_id_when_present() {
+        let mut a = message("1");
+        let mut b = message("1");
+        a.message = "hi".to_string();
+        b.message = "a totally different message".to_string();
+        assert_eq!(dedupe_key(&a), dedupe_key(&b));
+    }
+
+    #[test]
+    fn dedupe_key_falls_back_to_sender_and_content_without_an_id() {
+        let mut a = message("1");
+        a.id = None;
+        let mut b = message("1");
+        b.id = None;
+        assert_eq!(dedupe_key(&a), dedupe_key(&b));
+
+        let mut c = message("1");
+        c.id = None;
+        c.from = "carol".to_string();
+        assert_ne!(dedupe_key(&a), dedupe_key(&c));
+    }
+
+    #[test]
+    fn replayed_message_without_an_id_is_recognized_as_a_duplicate() {
+        let mut seen = std::collections::HashSet::new();
+        let mut first = message("1");
+        first.id = None;
+        let mut replay = message("1");
+        replay.id = None;
+        assert!(seen.insert(dedupe_key(&first)));
+        assert!(!seen.insert(dedupe_key(&replay)));
+    }
+
+    #[test]
+    fn status_glyph_upgrades_to_blue_only_once_read() {
+        assert_eq!(status_glyph(MessageStatus::Sending), ("🕐", "text-gray-400"));
+        assert_eq!(status_glyph(MessageStatus::Sent), ("✓", "text-gray-400"));
+        assert_eq!(status_glyph(MessageStatus::Delivered), ("✓✓", "text-gray-400"));
+        assert_eq!(status_glyph(MessageStatus::Read), ("✓✓", "text-blue-500"));
+    }
+}
@@ -1,4 +1,5 @@
-use web_sys::HtmlInputElement;
+use wasm_bindgen_futures::{spawn_local, JsFuture};
+use web_sys::{window, HtmlInputElement, Notification};
 use yew::functional::*;
 use yew::prelude::*;
 use yew_router::prelude::*;
@@ -6,9 +7,60 @@ use yew_router::prelude::*;
 use crate::Route;
 use crate::User;
 
+/// `localStorage` key the last-used username is persisted under, so the
+/// login screen can pre-fill it next time instead of asking again.
+const USERNAME_STORAGE_KEY: &str = "username";
+
+/// Reads the persisted username, defaulting to empty if unset or on
+/// browsers without `localStorage`.
+fn load_saved_username() -> String {
+    window()
+        .and_then(|w| w.local_storage().ok())
+        .flatten()
+        .and_then(|storage| storage.get_item(USERNAME_STORAGE_KEY).ok())
+        .flatten()
+        .unwrap_or_default()
+}
+
+/// Removes the persisted username — called on logout (see `Chat`'s
+/// `Msg::Logout`) so the next person to sign in on this browser isn't
+/// greeted with the previous user's name.
+pub(crate) fn clear_saved_username() {
+    if let Some(storage) = window().and_then(|w| w.local_storage().ok()).flatten() {
+        let _ = storage.remove_item(USERNAME_STORAGE_KEY);
+    }
+}
+
+/// Requests desktop notification permission and stores whether it was
+/// granted on `user`, so `Chat` can check it later without a second
+/// permission prompt. Fired once, from the login screen, since browsers
+/// only show the prompt in response to a user gesture like this button
+/// click.
+fn request_notification_permission(user: User) {
+    spawn_local(async move {
+        let promise = match Notification::request_permission() {
+            Ok(p) => p,
+            Err(e) => {
+                log::warn!("failed to request notification permission: {:?}", e);
+                return;
+            }
+        };
+        let granted = match JsFuture::from(promise).await {
+            Ok(result) => result.as_string().as_deref() == Some("granted"),
+            Err(e) => {
+                log::warn!("notification permission request rejected: {:?}", e);
+                false
+            }
+        };
+        user.notifications_enabled.set(granted);
+    });
+}
+
 #[function_component(Login)]
 pub fn login() -> Html {
-    let username = use_state(|| String::new());
+    let username = use_state(load_saved_username);
+    let token = use_state(|| String::new());
+    let remember_me = use_state(|| true);
     let user = use_context::<User>().expect("No context found.");
 
     let oninput = {
@@ -20,18 +72,60 @@ pub fn login() -> Html {
         })
     };
 
+    let oninput_token = {
+        let current_token = token.clone();
+
+        Callback::from(move |e: InputEvent| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            current_token.set(input.value());
+        })
+    };
+
+    let onchange_remember = {
+        let remember_me = remember_me.clone();
+
+        Callback::from(move |e: Event| {
+            let input: HtmlInputElement = e.target_unchecked_into();
+            remember_me.set(input.checked());
+        })
+    };
+
     let onclick = {
         let username = username.clone();
+        let token = token.clone();
+        let remember_me = remember_me.clone();
         let user = user.clone();
-        Callback::from(move |_| *user.username.borrow_mut() = (*username).clone())
+        Callback::from(move |_| {
+            *user.username.borrow_mut() = (*username).clone();
+            *user.token.borrow_mut() = if token.is_empty() {
+                None
+            } else {
+                Some((*token).clone())
+            };
+            if *remember_me {
+                if let Some(storage) = window().and_then(|w| w.local_storage().ok()).flatten() {
+                    let _ = storage.set_item(USERNAME_STORAGE_KEY, &username);
+                }
+            } else {
+                clear_saved_username();
+            }
+            request_notification_permission(user.clone());
+        })
     };
 
     html! {
        <div class="bg-gray-800 flex w-screen">
             <div class="container mx-auto flex flex-col justify-center items-center">
-                <form class="m-4 flex">
-                    <input {oninput} class="rounded-l-lg p-4 border-t mr-0 border-b border-l text-gray-800 border-gray-200 bg-white" placeholder="Username" />
-                    <Link<Route> to={Route::Chat}> <button {onclick} disabled={username.len()<1} class="px-8 rounded-r-lg bg-violet-600	  text-white font-bold p-4 uppercase border-violet-600 border-t border-b border-r" >{"Go Chatting!"}</button></Link<Route>>
+                <form class="m-4 flex flex-col items-center">
+                    <div class="flex">
+                        <input {oninput} value={(*username).clone()} class="rounded-l-lg p-4 border-t mr-0 border-b border-l text-gray-800 border-gray-200 bg-white" placeholder="Username" />
+                        <input oninput={oninput_token} type="password" class="p-4 border-t border-b text-gray-800 border-gray-200 bg-white" placeholder="Access token (optional)" />
+                        <Link<Route> to={Route::Chat}> <button {onclick} disabled={username.len()<1} class="px-8 rounded-r-lg bg-violet-600	  text-white font-bold p-4 uppercase border-violet-600 border-t border-b border-r" >{"Go Chatting!"}</button></Link<Route>>
+                    </div>
+                    <label class="mt-2 text-sm text-gray-300 flex items-center">
+                        <input type="checkbox" checked={*remember_me} onchange={onchange_remember} class="mr-2" />
+                        {"Remember me"}
+                    </label>
                 </form>
             </div>
         </div>
@@ -3,7 +3,7 @@
 mod components;
 mod services;
 
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 use wasm_bindgen::prelude::*;
@@ -13,6 +13,7 @@ use yew_router::prelude::*;
 
 use components::chat::Chat;
 use components::login::Login;
+use services::websocket::WebsocketService;
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
@@ -38,6 +39,67 @@ pub type User = Rc<UserInner>;
 #[derive(Debug, PartialEq)]
 pub struct UserInner {
     pub username: RefCell<String>,
+    /// Auth token collected on the login screen and attached to the
+    /// websocket handshake by `WebsocketService`, for deployments that sit
+    /// behind a gateway that requires one. `None` for local dev.
+    pub token: RefCell<Option<String>>,
+    /// Whether the user granted desktop notification permission, requested
+    /// once from the login screen. Checked by `Chat` before showing a
+    /// notification for a message that arrives while the tab is hidden.
+    pub notifications_enabled: Cell<bool>,
+}
+
+/// A single websocket connection shared by every component that needs it,
+/// via `ContextProvider`, instead of each one opening its own — so a rooms
+/// sidebar or presence widget added later attaches to the same socket
+/// `Chat` uses rather than spawning a second connection.
+///
+/// Built lazily via [`connect`](Self::connect) rather than at app startup,
+/// since the connection needs `user.token` — collected on the login screen,
+/// after `Main` has already rendered — to attach to the handshake for a
+/// gateway that gates on it. The first caller to connect with a given token
+/// gets a fresh connection; later callers get the same one back as long as
+/// the token hasn't changed (a plain route remount reusing the same login),
+/// and a changed token (a fresh login replacing the previous one) tears the
+/// old connection down and opens a newly authenticated one in its place.
+#[derive(Clone)]
+pub struct WsHandle(Rc<RefCell<Option<(Option<String>, Rc<WebsocketService>)>>>);
+
+impl WsHandle {
+    fn new() -> Self {
+        Self(Rc::new(RefCell::new(None)))
+    }
+
+    /// Returns the shared connection to `url`, creating it (or recreating it,
+    /// dropping the old one) if it doesn't exist yet or was last built with a
+    /// different `token`.
+    pub fn connect(&self, url: String, token: Option<String>) -> Rc<WebsocketService> {
+        let mut slot = self.0.borrow_mut();
+        if let Some((existing_token, service)) = slot.as_ref() {
+            if existing_token == &token {
+                return service.clone();
+            }
+        }
+        let service = Rc::new(WebsocketService::new(url, token.clone()));
+        *slot = Some((token, service.clone()));
+        service
+    }
+
+    /// Drops the cached connection, if any, so the next [`connect`](Self::connect)
+    /// call always builds a fresh `WebsocketService` instead of handing back
+    /// one a caller already closed. Needed because `close()` on the service
+    /// itself ends the socket but leaves it sitting in this cache, where a
+    /// later `connect()` with the same token (e.g. `None`, which isn't
+    /// persisted across a logout/login cycle) would otherwise reuse it.
+    pub fn invalidate(&self) {
+        *self.0.borrow_mut() = None;
+    }
+}
+
+impl PartialEq for WsHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
 }
 
 #[function_component(Main)]
@@ -45,16 +107,22 @@ fn main() -> Html {
     let ctx = use_state(|| {
         Rc::new(UserInner {
             username: RefCell::new("initial".into()),
+            token: RefCell::new(None),
+            notifications_enabled: Cell::new(false),
         })
     });
 
+    let wss = use_state(WsHandle::new);
+
     html! {
         <ContextProvider<User> context={(*ctx).clone()}>
-            <BrowserRouter>
-                <div class="flex w-screen h-screen">
-                    <Switch<Route> render={Switch::render(switch)}/>
-                </div>
-            </BrowserRouter>
+            <ContextProvider<WsHandle> context={(*wss).clone()}>
+                <BrowserRouter>
+                    <div class="flex w-screen h-screen">
+                        <Switch<Route> render={Switch::render(switch)}/>
+                    </div>
+                </BrowserRouter>
+            </ContextProvider<WsHandle>>
         </ContextProvider<User>>
     }
 }
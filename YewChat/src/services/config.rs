@@ -0,0 +1,99 @@
+//! Resolves the websocket server URL the app should connect to.
+//!
+//! Priority, highest first: a `?ws=` query parameter, a `ws_url` key in
+//! `localStorage`, then the `YEWCHAT_WS_URL` compile-time env var (falling
+//! back to the local dev server).
+//!
+//! Whichever endpoint wins, [`select_ws_url`] fills in a `ws://`/`wss://`
+//! scheme matching the page's own scheme if the configured endpoint didn't
+//! specify one, so serving the app over https doesn't leave it silently
+//! trying (and failing) to open a plain `ws://` connection.
+
+use web_sys::window;
+
+pub const DEFAULT_WS_URL: &str = "ws://127.0.0.1:8080";
+
+/// Resolves the websocket URL to connect to, or an error message fit to
+/// show the user if an override was supplied but isn't a valid `ws(s)://`
+/// URL.
+pub fn resolve_ws_url() -> Result<String, String> {
+    let endpoint = query_param("ws")
+        .or_else(local_storage_value)
+        .unwrap_or_else(|| {
+            option_env!("YEWCHAT_WS_URL")
+                .unwrap_or(DEFAULT_WS_URL)
+                .to_string()
+        });
+
+    let url = select_ws_url(page_is_secure(), &endpoint);
+
+    if url.starts_with("ws://") || url.starts_with("wss://") {
+        Ok(url)
+    } else {
+        Err(format!(
+            "invalid websocket URL '{}': must start with ws:// or wss://",
+            url
+        ))
+    }
+}
+
+/// Picks the final websocket URL for a configured `endpoint`, given whether
+/// the page itself was loaded over https. An `endpoint` with an explicit
+/// `ws://`/`wss://` scheme is returned unchanged (with a warning logged if
+/// it doesn't match the page's own scheme, since browsers block insecure
+/// websockets from a secure page); a bare host/path gets `wss://` on a
+/// secure page and `ws://` otherwise.
+pub fn select_ws_url(page_is_secure: bool, endpoint: &str) -> String {
+    if let Some(rest) = endpoint.strip_prefix("ws://") {
+        if page_is_secure {
+            log::warn!(
+                "configured ws:// endpoint '{}' will likely be blocked by the browser on a secure page",
+                endpoint
+            );
+        }
+        return format!("ws://{}", rest);
+    }
+    if endpoint.starts_with("wss://") {
+        return endpoint.to_string();
+    }
+
+    let scheme = if page_is_secure { "wss" } else { "ws" };
+    format!("{}://{}", scheme, endpoint)
+}
+
+/// Whether the page itself was loaded over `https:`.
+fn page_is_secure() -> bool {
+    window()
+        .and_then(|w| w.location().protocol().ok())
+        .map(|p| p == "https:")
+        .unwrap_or(false)
+}
+
+fn query_param(key: &str) -> Option<String> {
+    let location = window()?.location();
+    let search = location.search().ok()?;
+    web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get(key)
+}
+
+fn local_storage_value() -> Option<String> {
+    window()?.local_storage().ok()??.get_item("ws_url").ok()?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::select_ws_url;
+
+    #[test]
+    fn bare_host_gets_page_scheme() {
+        assert_eq!(select_ws_url(false, "chat.example.com"), "ws://chat.example.com");
+        assert_eq!(select_ws_url(true, "chat.example.com"), "wss://chat.example.com");
+    }
+
+    #[test]
+    fn explicit_scheme_is_respected() {
+        assert_eq!(select_ws_url(true, "ws://chat.example.com"), "ws://chat.example.com");
+        assert_eq!(select_ws_url(false, "wss://chat.example.com"), "wss://chat.example.com");
+    }
+}
@@ -1,47 +1,139 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use yew_agent::{Agent, AgentLink, Context, HandlerId};
 
-#[derive(Serialize, Deserialize, Debug)]
+use crate::services::protocol::{BusMessage, Delivery, Topic};
+
+/// How many of the most recent events `EventBus` keeps so a bridge that
+/// connects after messages have already arrived (e.g. after a route
+/// transition remounts a component) doesn't start on a blank slate — it
+/// gets these replayed to it in `connected()` before any live event.
+const REPLAY_BUFFER_SIZE: usize = 50;
+
+#[derive(Debug)]
 pub enum Request {
-    EventBusMsg(String),
+    EventBusMsg(BusMessage),
+    /// Narrows the sending bridge's subscription to just `Topic`, instead of
+    /// every topic (the default until the first `Subscribe`). Sending it
+    /// more than once accumulates topics rather than replacing them, so a
+    /// component that wants both `Users` and `Messages` just sends it
+    /// twice.
+    Subscribe(Topic),
 }
 
 pub struct EventBus {
     link: AgentLink<EventBus>,
-    subscribers: HashSet<HandlerId>,
+    /// `None` means no `Subscribe` has been sent yet, so this bridge gets
+    /// every event — the behavior every existing subscriber relies on
+    /// without having to opt in.
+    subscribers: HashMap<HandlerId, Option<HashSet<Topic>>>,
+    /// The last [`REPLAY_BUFFER_SIZE`] events, oldest first, replayed to
+    /// every newly connected subscriber.
+    recent: VecDeque<BusMessage>,
+}
+
+/// Pushes `event` onto `buffer`, evicting the oldest entry once `buffer`
+/// grows past [`REPLAY_BUFFER_SIZE`] — pulled out of `handle_input` so the
+/// overflow behavior can be unit tested without spinning up the agent.
+fn push_bounded(buffer: &mut VecDeque<BusMessage>, event: BusMessage) {
+    buffer.push_back(event);
+    if buffer.len() > REPLAY_BUFFER_SIZE {
+        buffer.pop_front();
+    }
 }
 
 impl Agent for EventBus {
     type Reach = Context<Self>;
     type Message = ();
     type Input = Request;
-    type Output = String;
+    type Output = Delivery;
 
     fn create(link: AgentLink<Self>) -> Self {
         Self {
             link,
-            subscribers: HashSet::new(),
+            subscribers: HashMap::new(),
+            recent: VecDeque::new(),
         }
     }
 
     fn update(&mut self, _msg: Self::Message) {}
 
-    fn handle_input(&mut self, msg: Self::Input, _id: HandlerId) {
+    fn handle_input(&mut self, msg: Self::Input, id: HandlerId) {
         match msg {
             Request::EventBusMsg(s) => {
-                for sub in self.subscribers.iter() {
-                    self.link.respond(*sub, s.clone())
+                for (sub, topics) in self.subscribers.iter() {
+                    let interested = match topics {
+                        None => true,
+                        Some(topics) => s.topic().map(|t| topics.contains(&t)).unwrap_or(true),
+                    };
+                    if interested {
+                        self.link.respond(
+                            *sub,
+                            Delivery {
+                                message: s.clone(),
+                                replayed: false,
+                            },
+                        )
+                    }
                 }
+                push_bounded(&mut self.recent, s);
+            }
+            Request::Subscribe(topic) => {
+                self.subscribers
+                    .entry(id)
+                    .or_insert_with(|| Some(HashSet::new()))
+                    .get_or_insert_with(HashSet::new)
+                    .insert(topic);
             }
         }
     }
 
     fn connected(&mut self, id: HandlerId) {
-        self.subscribers.insert(id);
+        self.subscribers.insert(id, None);
+        for message in self.recent.iter() {
+            self.link.respond(
+                id,
+                Delivery {
+                    message: message.clone(),
+                    replayed: true,
+                },
+            );
+        }
     }
 
     fn disconnected(&mut self, id: HandlerId) {
         self.subscribers.remove(&id);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::protocol::{MsgTypes, WebSocketMessage};
+
+    fn frame(attempt: u32) -> BusMessage {
+        BusMessage::Frame(WebSocketMessage {
+            attempt: Some(attempt),
+            ..WebSocketMessage::new(MsgTypes::Stalled)
+        })
+    }
+
+    #[test]
+    fn keeps_only_the_most_recent_events_once_full() {
+        let mut buffer = VecDeque::new();
+        for attempt in 0..(REPLAY_BUFFER_SIZE as u32 + 10) {
+            push_bounded(&mut buffer, frame(attempt));
+        }
+        assert_eq!(buffer.len(), REPLAY_BUFFER_SIZE);
+        assert_eq!(buffer.front(), Some(&frame(10)));
+        assert_eq!(buffer.back(), Some(&frame(REPLAY_BUFFER_SIZE as u32 + 9)));
+    }
+
+    #[test]
+    fn stays_under_the_cap_while_filling_up() {
+        let mut buffer = VecDeque::new();
+        for attempt in 0..(REPLAY_BUFFER_SIZE as u32 - 1) {
+            push_bounded(&mut buffer, frame(attempt));
+        }
+        assert_eq!(buffer.len(), REPLAY_BUFFER_SIZE - 1);
+    }
+}
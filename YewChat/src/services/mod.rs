@@ -1,2 +1,5 @@
+pub mod config;
 pub mod websocket;
 pub mod event_bus;
+pub mod protocol;
+pub mod reconnect;
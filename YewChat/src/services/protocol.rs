@@ -0,0 +1,405 @@
+//! The wire format shared between `WebsocketService` and every component
+//! that sends or receives over it. `WebSocketMessage` and `MsgTypes` used to
+//! be defined in `components::chat` and re-serialized ad hoc at every call
+//! site; they live here instead so the service can do that serialization
+//! (and parsing) exactly once, and so a future second consumer of the
+//! socket doesn't have to reach into `chat.rs` to speak the protocol.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MsgTypes {
+    Users,
+    Register,
+    Message,
+    ConnectionState,
+    SendFailure,
+    Typing,
+    Image,
+    Edit,
+    Delete,
+    React,
+    /// Pins a message to the room's "📌 Pinned" strip — `id` names the
+    /// message, `data` carries a JSON-encoded snippet of it so the strip can
+    /// still render the pin for a client whose local history doesn't have
+    /// that message loaded.
+    Pin,
+    /// Reverses a `Pin` — `id` names the message, no `data` payload.
+    Unpin,
+    Join,
+    Leave,
+    Metrics,
+    Stalled,
+    /// A client reporting the last message it has on screen — `id` is that
+    /// message's id, `data` is the reporting user's name (same shape as
+    /// `Typing`). Broadcast back out so every other client can update its
+    /// "seen by" labels.
+    Read,
+    /// The server confirming it accepted a submitted `Message` — `id` is
+    /// the id that was submitted with it. Distinct from the ack a sender
+    /// awaits directly through `send_with_ack`'s `{"ackId": ...}` reply;
+    /// this one goes out over the normal `EventBus` path so `Chat` can
+    /// advance that message's delivery status the same way it reacts to
+    /// any other frame.
+    Ack,
+    /// A client reporting its own online/away/offline status changed —
+    /// `data` is a JSON-encoded `{user, status}` payload (same convention as
+    /// `React`'s `ReactionPayload`), broadcast back out so every other
+    /// client can update that user's dot in the sidebar without waiting for
+    /// the next full `Users` resync.
+    Presence,
+    /// A request for (outgoing, `data` optionally a stringified limit) or
+    /// reply to (incoming, `data_array` of JSON-encoded `MessageData`) a
+    /// room's recent backlog — sent once on joining a room so a late
+    /// arrival doesn't see an empty pane.
+    History,
+    /// The server's reply to a `Register` whose `protocol_version` is too
+    /// old — `data` is the stringified minimum version it requires. See
+    /// [`parse_upgrade_required`]. The client should stop trying to
+    /// interpret any further frames rather than risk misparsing a wire
+    /// format it predates.
+    UpgradeRequired,
+    /// The server rejecting a client action — name taken, message too long,
+    /// rate limited, and the like. `data` is a JSON-encoded
+    /// `{code, message, ref_id}` payload; `ref_id` names the outgoing
+    /// message id the rejected action was about, if any, so `Chat` can flip
+    /// that message to the failed state in addition to toasting `message`.
+    Error,
+    /// A `messageType` this build doesn't recognize — kept instead of
+    /// rejecting the whole frame so a server that's shipped a new message
+    /// type this client predates (e.g. `"reaction"` before `React` existed)
+    /// doesn't take down every older client still catching up. `Chat` logs
+    /// and ignores it rather than panicking or erroring the connection.
+    #[serde(other)]
+    Unknown,
+}
+
+/// This client's wire-protocol version, sent as `protocol_version` on every
+/// `Register` — bumped whenever `MsgTypes` or `WebSocketMessage` changes in
+/// a way an older build can't parse safely, so the server can tell a
+/// too-old client to upgrade instead of sending it frames it'll silently
+/// mis-decode.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Result of checking this client's `PROTOCOL_VERSION` against the minimum
+/// a `MsgTypes::UpgradeRequired` frame's `data` asked for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeOutcome {
+    /// This client's version satisfies the server's minimum, or the frame
+    /// didn't carry a parseable minimum at all — see `parse_upgrade_required`.
+    Accepted,
+    /// This client is older than the server's minimum; the caller should
+    /// show a blocking overlay telling the user to refresh.
+    UpgradeRequired { minimum_version: u32 },
+}
+
+/// Parses a `MsgTypes::UpgradeRequired` frame's `data` (a stringified
+/// minimum version) into a [`HandshakeOutcome`]. A frame whose `data` isn't
+/// a valid version number — including `None`, which is what a server that
+/// predates this handshake entirely would send if it echoed the frame back
+/// unchanged some other way — is treated as `Accepted` rather than rejected,
+/// since there's no concrete minimum to enforce.
+pub fn parse_upgrade_required(data: Option<&str>) -> HandshakeOutcome {
+    let Some(minimum_version) = data.and_then(|d| d.parse::<u32>().ok()) else {
+        return HandshakeOutcome::Accepted;
+    };
+    if PROTOCOL_VERSION >= minimum_version {
+        HandshakeOutcome::Accepted
+    } else {
+        HandshakeOutcome::UpgradeRequired { minimum_version }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebSocketMessage {
+    pub message_type: MsgTypes,
+    pub data_array: Option<Vec<String>>,
+    pub data: Option<String>,
+    pub timestamp: Option<f64>,
+    pub attempt: Option<u32>,
+    /// The message an `Edit` targets, or the id assigned to an outgoing
+    /// `Message` so it can be edited later.
+    pub id: Option<String>,
+    /// Id of the message a new `Message` is replying to, echoed back into
+    /// the broadcast `MessageData` server-side.
+    pub reply_to: Option<String>,
+    /// A "sender: first ~80 chars" snippet of the message named by
+    /// `reply_to`, computed by the sender at reply time and likewise echoed
+    /// back verbatim. Lets a recipient who doesn't have `reply_to` in their
+    /// own history (it scrolled out, or they joined after it was sent) still
+    /// show something for the quote instead of just not rendering it.
+    pub reply_snippet: Option<String>,
+    /// Id of the root message this `Message` belongs to as a thread reply,
+    /// echoed back into the broadcast `MessageData` server-side the same way
+    /// `reply_to` is. `None` for an ordinary top-level message.
+    pub thread_root: Option<String>,
+    /// Username to deliver this `Message` to privately instead of
+    /// broadcasting it to everyone in `room`. `None` for an ordinary
+    /// channel message.
+    pub to: Option<String>,
+    /// Usernames this `Message` is whispered to — delivered to the sender
+    /// plus everyone named here instead of the whole `room`. Empty for an
+    /// ordinary message (public, or a `to`-style one-on-one DM).
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    /// Seconds after which the receiving client should prune this `Message`
+    /// from its view, or `None` for a message that never expires. See
+    /// `components::chat::MessageData::expires_in`.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+    /// Channel this message belongs to — every client-originated frame
+    /// (register, join/leave, chat message, typing, ...) carries the room
+    /// it's happening in.
+    #[serde(default = "default_room")]
+    pub room: String,
+    /// On an outgoing `History` request, the oldest message id already
+    /// loaded for this room — asks the server for the page just before it
+    /// instead of the newest page again. `None` for the first page of a
+    /// room's backlog.
+    pub before: Option<String>,
+    /// Sent on `Register` as this client's [`PROTOCOL_VERSION`], so the
+    /// server can reply with `UpgradeRequired` instead of frames a too-old
+    /// client would misparse.
+    pub protocol_version: Option<u32>,
+}
+
+/// The channel a client is in before it's ever explicitly switched — also
+/// the room `Register` joins on first connect.
+pub fn default_room() -> String {
+    "general".to_string()
+}
+
+impl WebSocketMessage {
+    /// A bare `message_type` frame with every other field empty and `room`
+    /// defaulted, for callers that only need to set one or two fields —
+    /// `WebSocketMessage { message_type: ..., data: Some(...), ..WebSocketMessage::new(...) }`
+    /// instead of writing out all nine fields every time.
+    pub fn new(message_type: MsgTypes) -> Self {
+        Self {
+            message_type,
+            data_array: None,
+            data: None,
+            timestamp: None,
+            attempt: None,
+            id: None,
+            reply_to: None,
+            reply_snippet: None,
+            thread_root: None,
+            to: None,
+            recipients: Vec::new(),
+            expires_in: None,
+            room: default_room(),
+            before: None,
+            protocol_version: None,
+        }
+    }
+}
+
+/// A frame delivered to `EventBus` subscribers: either a successfully
+/// decoded [`WebSocketMessage`], or a [`BusMessage::ParseError`] when a
+/// frame off the wire didn't decode as one — surfaced as a real event
+/// instead of silently dropped, so `Chat` can show the user something went
+/// wrong rather than just never updating.
+///
+/// `WebSocketMessage::data` for message-specific payloads (`Message`'s
+/// `MessageData`, `React`'s reaction, `Metrics`'s snapshot) is decoded a
+/// second time by each subscriber rather than here. Those payload shapes
+/// are meaningful only to whoever cares about that particular
+/// `message_type` — `Chat` today, potentially a rooms list or notification
+/// agent tomorrow — so this layer only guarantees the envelope parses;
+/// every subscriber already does its second decode the same
+/// never-`unwrap` way this one does, matching on `Result` and dropping the
+/// frame with a `log::warn!` rather than panicking on something malformed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum BusMessage {
+    Frame(WebSocketMessage),
+    ParseError(String),
+}
+
+/// The topics an `EventBus` bridge can subscribe to via
+/// `event_bus::Request::Subscribe`, so a component only re-renders on the
+/// events it actually cares about — a future user sidebar wouldn't need to
+/// wake up on every chat message, for instance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    /// Roster changes: `Users`, `Join`, `Leave`, `Presence`.
+    Users,
+    /// Everything shown in the message pane: `Message`, `Edit`, `Delete`,
+    /// `React`, `Image`, `Typing`, `SendFailure`, `Read`, `Ack`, `History`.
+    Messages,
+    /// Link health: `ConnectionState`, `Metrics`, `Stalled`, `UpgradeRequired`.
+    Connection,
+}
+
+impl BusMessage {
+    /// Which `Topic` this event belongs to, or `None` for
+    /// `BusMessage::ParseError`, `MsgTypes::Register`, `MsgTypes::Error` and
+    /// `MsgTypes::Unknown` — delivered to every subscriber regardless of
+    /// their `Subscribe` calls, since a malformed frame, a stray register
+    /// echo, a server error (which could be about a roster, messaging, or
+    /// connection action depending on what was rejected), or a message type
+    /// this build doesn't recognize yet isn't something any one topic owns
+    /// (and a future debug panel watching for `Unknown` frames shouldn't
+    /// have to subscribe to all three topics to see them).
+    pub fn topic(&self) -> Option<Topic> {
+        let BusMessage::Frame(frame) = self else {
+            return None;
+        };
+        match frame.message_type {
+            MsgTypes::Users | MsgTypes::Join | MsgTypes::Leave | MsgTypes::Presence => Some(Topic::Users),
+            MsgTypes::Message
+            | MsgTypes::Edit
+            | MsgTypes::Delete
+            | MsgTypes::React
+            | MsgTypes::Image
+            | MsgTypes::Typing
+            | MsgTypes::SendFailure
+            | MsgTypes::Read
+            | MsgTypes::Ack
+            | MsgTypes::History => Some(Topic::Messages),
+            MsgTypes::ConnectionState | MsgTypes::Metrics | MsgTypes::Stalled | MsgTypes::UpgradeRequired => {
+                Some(Topic::Connection)
+            }
+            MsgTypes::Register | MsgTypes::Error | MsgTypes::Unknown => None,
+        }
+    }
+}
+
+/// One event delivered from `EventBus` to a subscriber, together with
+/// whether it's a replay from the agent's buffer — queued before this
+/// subscriber connected — rather than one just off the wire. A component
+/// should still fold a replayed event into its state as normal, but skip
+/// anything that only makes sense on arrival: a sound, a desktop
+/// notification, a title flash. Those already happened for whoever was
+/// connected the first time around.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Delivery {
+    pub message: BusMessage,
+    pub replayed: bool,
+}
+
+/// Parses a raw frame off the wire into a [`WebSocketMessage`], returning a
+/// message fit for `log::warn!` (and a [`BusMessage::ParseError`]) instead
+/// of panicking on anything malformed.
+pub fn parse_frame(data: &str) -> Result<WebSocketMessage, String> {
+    serde_json::from_str(data).map_err(|e| format!("dropping malformed websocket message {:?}: {}", data, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trips(message_type: MsgTypes) {
+        let message = WebSocketMessage {
+            message_type,
+            data_array: Some(vec!["a".to_string(), "b".to_string()]),
+            data: Some("payload".to_string()),
+            timestamp: Some(1.0),
+            attempt: Some(2),
+            id: Some("id".to_string()),
+            reply_to: Some("reply".to_string()),
+            reply_snippet: Some("alice: hello there".to_string()),
+            thread_root: Some("root".to_string()),
+            to: Some("bob".to_string()),
+            recipients: vec!["carol".to_string(), "dave".to_string()],
+            expires_in: Some(30),
+            room: "general".to_string(),
+            before: Some("oldest-id".to_string()),
+            protocol_version: Some(PROTOCOL_VERSION),
+        };
+        let serialized = serde_json::to_string(&message).unwrap();
+        let parsed = parse_frame(&serialized).unwrap();
+        assert_eq!(parsed, message);
+    }
+
+    #[test]
+    fn round_trips_every_message_type() {
+        round_trips(MsgTypes::Users);
+        round_trips(MsgTypes::Register);
+        round_trips(MsgTypes::Message);
+        round_trips(MsgTypes::ConnectionState);
+        round_trips(MsgTypes::SendFailure);
+        round_trips(MsgTypes::Typing);
+        round_trips(MsgTypes::Image);
+        round_trips(MsgTypes::Edit);
+        round_trips(MsgTypes::Delete);
+        round_trips(MsgTypes::React);
+        round_trips(MsgTypes::Pin);
+        round_trips(MsgTypes::Unpin);
+        round_trips(MsgTypes::Join);
+        round_trips(MsgTypes::Leave);
+        round_trips(MsgTypes::Metrics);
+        round_trips(MsgTypes::Stalled);
+        round_trips(MsgTypes::Read);
+        round_trips(MsgTypes::Ack);
+        round_trips(MsgTypes::Presence);
+        round_trips(MsgTypes::History);
+        round_trips(MsgTypes::UpgradeRequired);
+        round_trips(MsgTypes::Error);
+        round_trips(MsgTypes::Unknown);
+    }
+
+    #[test]
+    fn novel_message_type_parses_as_unknown_instead_of_failing() {
+        let parsed = parse_frame(r#"{"messageType":"reaction","room":"general"}"#).unwrap();
+        assert_eq!(parsed.message_type, MsgTypes::Unknown);
+        assert_eq!(BusMessage::Frame(parsed).topic(), None);
+    }
+
+    #[test]
+    fn handshake_accepts_a_minimum_at_or_below_ours() {
+        assert_eq!(parse_upgrade_required(Some("1")), HandshakeOutcome::Accepted);
+        assert_eq!(parse_upgrade_required(Some("0")), HandshakeOutcome::Accepted);
+    }
+
+    #[test]
+    fn handshake_rejects_a_minimum_above_ours() {
+        assert_eq!(
+            parse_upgrade_required(Some("99")),
+            HandshakeOutcome::UpgradeRequired { minimum_version: 99 }
+        );
+    }
+
+    #[test]
+    fn handshake_accepts_a_missing_or_unparseable_minimum() {
+        assert_eq!(parse_upgrade_required(None), HandshakeOutcome::Accepted);
+        assert_eq!(parse_upgrade_required(Some("not-a-number")), HandshakeOutcome::Accepted);
+    }
+
+    #[test]
+    fn room_defaults_when_absent() {
+        let parsed = parse_frame(r#"{"messageType":"message","dataArray":null,"data":null,"timestamp":null,"attempt":null,"id":null,"replyTo":null,"to":null}"#).unwrap();
+        assert_eq!(parsed.room, "general");
+    }
+
+    #[test]
+    fn recipients_defaults_to_empty_when_absent() {
+        let parsed = parse_frame(r#"{"messageType":"message","to":"bob"}"#).unwrap();
+        assert!(parsed.recipients.is_empty());
+    }
+
+    #[test]
+    fn expires_in_defaults_to_none_when_absent() {
+        let parsed = parse_frame(r#"{"messageType":"message","to":"bob"}"#).unwrap();
+        assert_eq!(parsed.expires_in, None);
+    }
+
+    #[test]
+    fn garbage_is_rejected_instead_of_panicking() {
+        assert!(parse_frame("not json at all").is_err());
+        assert!(parse_frame(r#"{"notEvenAMessageType":true}"#).is_err());
+    }
+
+    #[test]
+    fn truncated_json_is_rejected_instead_of_panicking() {
+        assert!(parse_frame(r#"{"messageType":"message","data":"hel"#).is_err());
+    }
+
+    #[test]
+    fn wrong_field_type_is_rejected_instead_of_panicking() {
+        assert!(parse_frame(r#"{"messageType":"message","data":42}"#).is_err());
+        assert!(parse_frame(r#"{"messageType":"message","attempt":"not-a-number"}"#).is_err());
+    }
+}
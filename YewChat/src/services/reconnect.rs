@@ -0,0 +1,106 @@
+//! How long `WebsocketService`'s `connect_loop` waits before its next
+//! reconnect attempt, and whether it should attempt one at all — pulled out
+//! of `websocket.rs` into a trait so different deployments can plug in
+//! different behavior (a dev environment that wants to fail fast instead of
+//! quietly retrying forever, a production one that wants a capped backoff
+//! and an eventual give-up) without `connect_loop` itself knowing which.
+
+use std::time::Duration;
+
+/// Decides how long to wait before the next reconnect attempt. `attempt` is
+/// the number of consecutive failures since the last successful connection
+/// (or since the last manual [`WebsocketService::retry`](super::websocket::WebsocketService::retry)),
+/// starting at `0` for the very first attempt. `None` means give up —
+/// `connect_loop` stops attempting to reconnect and parks until `retry` is
+/// called.
+pub trait ReconnectPolicy {
+    fn next_delay(&mut self, attempt: u32) -> Option<Duration>;
+}
+
+/// Doubles the delay after every attempt, up to `max`. Retries forever
+/// unless `max_attempts` is set, in which case `next_delay` gives up once
+/// `attempt` reaches it.
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+    max_attempts: Option<u32>,
+}
+
+impl ExponentialBackoff {
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max, max_attempts: None }
+    }
+
+    /// Gives up once `attempt` reaches `max_attempts`, instead of retrying
+    /// indefinitely.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+}
+
+impl Default for ExponentialBackoff {
+    /// The delays this service has always used: 1s doubling up to 30s, with
+    /// no give-up threshold.
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), Duration::from_secs(30))
+    }
+}
+
+impl ReconnectPolicy for ExponentialBackoff {
+    fn next_delay(&mut self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max_attempts| attempt >= max_attempts) {
+            return None;
+        }
+        let delay = self.initial.saturating_mul(1u32 << attempt.min(20));
+        Some(delay.min(self.max))
+    }
+}
+
+/// Gives up after the very first failure — no reconnect attempts at all.
+#[derive(Default)]
+pub struct NoRetry;
+
+impl ReconnectPolicy for NoRetry {
+    fn next_delay(&mut self, _attempt: u32) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn doubles_every_attempt_up_to_the_cap() {
+        let mut policy = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_millis(800));
+        assert_eq!(policy.next_delay(0), Some(Duration::from_millis(100)));
+        assert_eq!(policy.next_delay(1), Some(Duration::from_millis(200)));
+        assert_eq!(policy.next_delay(2), Some(Duration::from_millis(400)));
+        assert_eq!(policy.next_delay(3), Some(Duration::from_millis(800)));
+        assert_eq!(policy.next_delay(4), Some(Duration::from_millis(800)));
+    }
+
+    #[test]
+    fn retries_forever_without_a_max_attempts() {
+        let mut policy = ExponentialBackoff::default();
+        for attempt in 0..50 {
+            assert!(policy.next_delay(attempt).is_some());
+        }
+    }
+
+    #[test]
+    fn gives_up_past_max_attempts() {
+        let mut policy = ExponentialBackoff::new(Duration::from_millis(100), Duration::from_secs(30))
+            .with_max_attempts(3);
+        assert!(policy.next_delay(0).is_some());
+        assert!(policy.next_delay(1).is_some());
+        assert!(policy.next_delay(2).is_some());
+        assert_eq!(policy.next_delay(3), None);
+    }
+
+    #[test]
+    fn no_retry_gives_up_immediately() {
+        assert_eq!(NoRetry.next_delay(0), None);
+    }
+}
@@ -1,53 +1,978 @@
-use futures::{channel::mpsc::Sender, SinkExt, StreamExt};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::io::{Read, Write};
+use std::rc::Rc;
+use std::time::Duration;
+
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use futures::{
+    channel::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
+    future::FutureExt,
+    pin_mut, select, SinkExt, StreamExt,
+};
+use gloo_timers::future::TimeoutFuture;
+use js_sys::Date;
 use reqwasm::websocket::{futures::WebSocket, Message};
+use serde::{Deserialize, Serialize};
+use web_sys::window;
 
 use wasm_bindgen_futures::spawn_local;
 use yew_agent::Dispatched;
 
 use crate::services::event_bus::{EventBus, Request};
+use crate::services::protocol::{self, BusMessage, MsgTypes, WebSocketMessage};
+use crate::services::reconnect::{ExponentialBackoff, ReconnectPolicy};
+
+/// Cap on the outbound queue used while disconnected; past this the
+/// oldest queued message is dropped to make room for the newest one.
+const MAX_QUEUED_MESSAGES: usize = 100;
+
+/// Default for how often to ping an idle connection — see
+/// [`new_with_config`](WebsocketService::new_with_config) to use a
+/// different interval — and, together with [`READ_TIMEOUT_GRACE_MS`], how
+/// much longer than that to go without receiving *any* frame — not just a
+/// pong reply — before the connection is considered stalled and
+/// force-reconnected. A half-open TCP connection can keep accepting writes
+/// while never delivering another byte back, so the watchdog counts all
+/// incoming traffic, not just pongs. Paused while the tab is hidden (see
+/// [`document_hidden`]) so a backgrounded tab that a server intentionally
+/// stays quiet with doesn't churn reconnects.
+const PING_INTERVAL_MS: u32 = 15_000;
+const READ_TIMEOUT_GRACE_MS: u32 = 5_000;
+
+/// A connection that closes this fast, this many times in a row right
+/// after being opened, never having received a single frame, looks less
+/// like a flaky network and more like the gateway rejecting the token on
+/// every attempt — browsers don't expose the handshake's HTTP status, so
+/// this timing heuristic is the closest we get to spotting a 401.
+const FAST_FAIL_MS: f64 = 500.0;
+const MAX_FAST_FAILS: u32 = 3;
+
+/// How long [`WebsocketService::send_with_ack`] waits for a matching `ack`
+/// frame before giving up on it as failed.
+const ACK_TIMEOUT_MS: u32 = 5_000;
+
+/// Cap on how many messages are buffered per room while it has no active
+/// [`WebsocketService::subscribe`] subscriber, so switching to a room you
+/// haven't looked at in a while still shows a few recent lines instead of
+/// nothing.
+const MAX_ROOM_BUFFER: usize = 20;
+
+/// Outgoing `data` payloads shorter than this aren't worth deflating —
+/// the compressed form plus base64 overhead would likely be bigger.
+const COMPRESSION_THRESHOLD_BYTES: usize = 256;
+
+/// The capability frame sent right after a connection opens and echoed
+/// back by servers that understand compressed `data` payloads. Anything
+/// else (including no reply at all) is treated as "unsupported", so this
+/// stays interoperable with servers that predate the feature.
+const COMPRESSION_CAPABILITY: &str = "compression";
+
+/// Lifecycle of the underlying websocket connection, mirrored to the UI
+/// through the `EventBus` and queryable synchronously via
+/// [`WebsocketService::state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Open,
+    Closed,
+    Reconnecting,
+    /// Repeated near-instant disconnects with a token configured; treated
+    /// as a rejected handshake rather than something backoff will fix, so
+    /// `connect_loop` gives up instead of retrying forever.
+    Unauthorized,
+    /// The configured [`ReconnectPolicy`] gave up. Unlike `Unauthorized`,
+    /// `connect_loop` doesn't return on this — it parks until
+    /// [`WebsocketService::retry`] is called, then resumes from attempt 0.
+    Failed,
+}
+
+impl ConnectionState {
+    /// Whether it's currently safe to send on the socket — used by
+    /// `Chat` to gate the send button so users don't fire messages into
+    /// a dead connection.
+    pub fn is_open(&self) -> bool {
+        matches!(self, ConnectionState::Open)
+    }
+}
+
+/// Snapshot of traffic and timing stats returned by
+/// [`WebsocketService::metrics`]. The unprefixed fields cover only the
+/// current connection and reset to zero on every reconnect; the `total_*`
+/// fields accumulate across every connection this service has made.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionMetrics {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Round-trip time of the most recent keepalive ping, or `None` before
+    /// the first pong of this connection has come back.
+    pub last_latency_ms: Option<u32>,
+    /// Milliseconds since this connection opened, or `0.0` while not
+    /// currently connected.
+    pub uptime_ms: f64,
+    pub total_bytes_sent: u64,
+    pub total_bytes_received: u64,
+    pub total_messages_sent: u64,
+    pub total_messages_received: u64,
+}
+
+type Queue = Rc<RefCell<VecDeque<String>>>;
+
+/// Outstanding [`WebsocketService::send_with_ack`] calls, keyed by the id
+/// the caller assigned the outgoing message, resolved when a matching
+/// `{"messageType":"ack","id":...}` frame comes back.
+type PendingAcks = Rc<RefCell<HashMap<String, oneshot::Sender<Result<(), String>>>>>;
+
+/// Per-room subscribers registered via [`WebsocketService::subscribe`]. A
+/// room's entry is only present while something is listening to it.
+type RoomSubscribers = Rc<RefCell<HashMap<String, Sender<String>>>>;
+
+/// Messages for a room that arrived with nobody subscribed to it yet,
+/// capped at [`MAX_ROOM_BUFFER`] and replayed to the next subscriber.
+type RoomBuffers = Rc<RefCell<HashMap<String, VecDeque<String>>>>;
+
+/// The most recent frame passed to [`WebsocketService::register`], resent
+/// by `connect_loop` on every successful connection (first connect and
+/// every reconnect after) so the server always knows who's on the other
+/// end, even after a hiccup it never saw a `Leave` for.
+type RegisterPayload = Rc<RefCell<Option<String>>>;
+
+/// Backing counters for [`ConnectionMetrics`], updated as frames cross the
+/// socket in `connect_loop`. `connected_at` is a `Date::now()` timestamp,
+/// not a duration, so [`WebsocketService::metrics`] can derive uptime from
+/// it on demand rather than ticking a counter every frame.
+#[derive(Default)]
+struct MetricsInner {
+    bytes_sent: u64,
+    bytes_received: u64,
+    messages_sent: u64,
+    messages_received: u64,
+    connected_at: f64,
+    last_latency_ms: Option<u32>,
+    total_bytes_sent: u64,
+    total_bytes_received: u64,
+    total_messages_sent: u64,
+    total_messages_received: u64,
+}
+
+type Metrics = Rc<RefCell<MetricsInner>>;
 
 pub struct WebsocketService {
     pub tx: Sender<String>,
+    state: Rc<Cell<ConnectionState>>,
+    reconnect_attempt: Rc<Cell<u32>>,
+    queue: Queue,
+    pending_acks: PendingAcks,
+    room_subscribers: RoomSubscribers,
+    room_buffers: RoomBuffers,
+    /// Whether the current connection's peer echoed back the compression
+    /// capability frame. Reset to `false` on every reconnect until
+    /// renegotiated, so a server that gets swapped out mid-session for one
+    /// that doesn't support compression doesn't get compressed frames.
+    compression_supported: Rc<Cell<bool>>,
+    metrics: Metrics,
+    register_payload: RegisterPayload,
+    retry_tx: Sender<()>,
+}
+
+impl Drop for WebsocketService {
+    /// Closes the channel feeding `connect_loop`, which ends the loop at
+    /// its next `in_rx.next()` poll and drops the `reqwasm` socket (and
+    /// with it the read/write halves), sending a close frame. Nothing
+    /// re-spawns once this service is gone, so a fresh `Chat` needs a
+    /// fresh `WebsocketService`.
+    fn drop(&mut self) {
+        self.tx.close_channel();
+    }
 }
 
 impl WebsocketService {
-    pub fn new() -> Self {
-        let ws = WebSocket::open("ws://127.0.0.1:8080").unwrap();
+    /// Opens a connection to `url`, attaching `token` (if any) as a query
+    /// parameter so gateways that gate the upgrade on it see it before
+    /// `Register` ever gets a chance to run. Reconnects with the default
+    /// [`ExponentialBackoff`] policy — see [`new_with_policy`](Self::new_with_policy)
+    /// to use a different one.
+    ///
+    /// `url` is the caller's responsibility to resolve — most callers want
+    /// [`config::resolve_ws_url`](crate::services::config::resolve_ws_url),
+    /// which already picks it from a `?ws=` override, `localStorage`, or the
+    /// `YEWCHAT_WS_URL` build-time env var. Logs an error (but still tries
+    /// to connect) if `url` doesn't start with `ws://` or `wss://`, since
+    /// that's almost always a misconfiguration that's easier to miss once
+    /// the connection is silently just not coming up.
+    pub fn new(url: String, token: Option<String>) -> Self {
+        if !url.starts_with("ws://") && !url.starts_with("wss://") {
+            log::error!("websocket url '{}' has an unexpected scheme, expected ws:// or wss://", url);
+        }
+        Self::new_with_policy(url, token, Box::new(ExponentialBackoff::default()))
+    }
 
-        let (mut write, mut read) = ws.split();
+    /// Like [`new`](Self::new), but reconnects according to `policy` instead
+    /// of the default backoff — e.g. [`NoRetry`](crate::services::reconnect::NoRetry)
+    /// for a dev environment that would rather fail fast than retry quietly
+    /// in the background.
+    pub fn new_with_policy(url: String, token: Option<String>, policy: Box<dyn ReconnectPolicy>) -> Self {
+        Self::new_with_config(url, token, policy, PING_INTERVAL_MS)
+    }
+
+    /// Like [`new_with_policy`](Self::new_with_policy), but pings the
+    /// connection every `ping_interval_ms` instead of the default
+    /// [`PING_INTERVAL_MS`] — e.g. a shorter interval for a flaky network
+    /// that needs to notice a stall sooner, at the cost of more keepalive
+    /// traffic.
+    pub fn new_with_config(url: String, token: Option<String>, policy: Box<dyn ReconnectPolicy>, ping_interval_ms: u32) -> Self {
+        let has_token = token.as_deref().is_some_and(|t| !t.is_empty());
+        let url = append_token(&url, token.as_deref());
+        let (in_tx, in_rx) = futures::channel::mpsc::channel::<String>(1000);
+        let (retry_tx, retry_rx) = futures::channel::mpsc::channel::<()>(1);
+        let state = Rc::new(Cell::new(ConnectionState::Connecting));
+        let reconnect_attempt = Rc::new(Cell::new(0));
+        let queue: Queue = Rc::new(RefCell::new(VecDeque::new()));
+        let pending_acks: PendingAcks = Rc::new(RefCell::new(HashMap::new()));
+        let room_subscribers: RoomSubscribers = Rc::new(RefCell::new(HashMap::new()));
+        let room_buffers: RoomBuffers = Rc::new(RefCell::new(HashMap::new()));
+        let compression_supported = Rc::new(Cell::new(false));
+        let metrics: Metrics = Rc::new(RefCell::new(MetricsInner::default()));
+        let register_payload: RegisterPayload = Rc::new(RefCell::new(None));
+        spawn_local(connect_loop(
+            url,
+            has_token,
+            in_rx,
+            retry_rx,
+            policy,
+            state.clone(),
+            reconnect_attempt.clone(),
+            queue.clone(),
+            pending_acks.clone(),
+            room_subscribers.clone(),
+            room_buffers.clone(),
+            compression_supported.clone(),
+            metrics.clone(),
+            register_payload.clone(),
+            ping_interval_ms,
+        ));
+
+        Self {
+            tx: in_tx,
+            state,
+            reconnect_attempt,
+            queue,
+            pending_acks,
+            room_subscribers,
+            room_buffers,
+            compression_supported,
+            metrics,
+            register_payload,
+            retry_tx,
+        }
+    }
+
+    /// Wakes `connect_loop` back up after the [`ReconnectPolicy`] gave up
+    /// (see [`ConnectionState::Failed`]) — the manual "try again" a user
+    /// triggers from the full-pane error `Chat` renders in that state. A
+    /// no-op if the connection isn't currently in that state.
+    pub fn retry(&self) {
+        let _ = self.retry_tx.clone().try_send(());
+    }
+
+    /// Tears down the connection for good — used on an explicit logout,
+    /// where we want the socket gone right away rather than waiting on
+    /// this service to be dropped along with the rest of `Chat`. Does the
+    /// same thing `Drop` does: closing `tx` ends `connect_loop` at its next
+    /// poll, which drops the underlying socket (sending a close frame) and
+    /// the read/write halves with it. Safe to call more than once; later
+    /// calls are no-ops since the channel is already closed.
+    pub fn close(&self) {
+        self.tx.clone().close_channel();
+    }
+
+    /// Current connection state, readable without waiting on the
+    /// `EventBus` round trip — e.g. to decide whether the send button
+    /// should be disabled.
+    pub fn state(&self) -> ConnectionState {
+        self.state.get()
+    }
+
+    /// How many reconnect attempts have been made since the last time the
+    /// socket was open. Resets to zero once a connection succeeds.
+    pub fn reconnect_attempt(&self) -> u32 {
+        self.reconnect_attempt.get()
+    }
+
+    /// Traffic and timing stats for the current connection, plus totals
+    /// across every connection this service has made. Also emitted
+    /// periodically over the `EventBus` as a `MsgTypes::Metrics` frame, so
+    /// most callers won't need to poll this directly.
+    pub fn metrics(&self) -> ConnectionMetrics {
+        let m = self.metrics.borrow();
+        let uptime_ms = if self.state.get().is_open() {
+            Date::now() - m.connected_at
+        } else {
+            0.0
+        };
+        ConnectionMetrics {
+            bytes_sent: m.bytes_sent,
+            bytes_received: m.bytes_received,
+            messages_sent: m.messages_sent,
+            messages_received: m.messages_received,
+            last_latency_ms: m.last_latency_ms,
+            uptime_ms,
+            total_bytes_sent: m.total_bytes_sent,
+            total_bytes_received: m.total_bytes_received,
+            total_messages_sent: m.total_messages_sent,
+            total_messages_received: m.total_messages_received,
+        }
+    }
+
+    /// Remembers `message` as the registration frame to automatically
+    /// resend on every future connection — including reconnects the caller
+    /// has no other hook into — so the server never mistakes a dropped and
+    /// re-established connection for the user having left. Does not send
+    /// `message` itself; the very next `connect_loop` iteration to reach
+    /// `ConnectionState::Open` (imminent if the socket isn't up yet) does.
+    pub fn register(&self, message: WebSocketMessage) {
+        *self.register_payload.borrow_mut() = Some(to_wire(&message));
+    }
+
+    /// Sends `message` if the socket is open, otherwise buffers it to be
+    /// flushed in order once the connection comes back. Returns `true` if
+    /// the message was buffered rather than sent immediately, so callers
+    /// can render it in a "pending" style.
+    pub fn send(&self, message: WebSocketMessage) -> bool {
+        let msg = maybe_compress(&to_wire(&message), self.compression_supported.get());
+        if self.state.get().is_open() {
+            if let Err(e) = self.tx.clone().try_send(msg.clone()) {
+                log::debug!("send failed, queueing instead: {:?}", e);
+                self.enqueue(msg);
+                return true;
+            }
+            return false;
+        }
+        self.enqueue(msg);
+        true
+    }
+
+    /// How many messages are currently buffered waiting for the
+    /// connection to come back.
+    pub fn queued_len(&self) -> usize {
+        self.queue.borrow().len()
+    }
+
+    /// Sends `message` (tagged with `id` by the caller) the same way
+    /// [`send`](Self::send) does, but resolves once a matching `ack` frame
+    /// comes back from the server instead of once it merely enters the
+    /// local channel — good enough to tell "queued locally" and "the server
+    /// actually saw it" apart. Times out after a few seconds, and resolves
+    /// to an error if the connection drops before the ack arrives.
+    pub fn send_with_ack(
+        &self,
+        id: String,
+        message: WebSocketMessage,
+    ) -> impl std::future::Future<Output = Result<(), String>> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.pending_acks.borrow_mut().insert(id.clone(), ack_tx);
+        self.send(message);
+        let pending_acks = self.pending_acks.clone();
+        async move {
+            let ack = ack_rx.fuse();
+            let timeout = TimeoutFuture::new(ACK_TIMEOUT_MS).fuse();
+            pin_mut!(ack, timeout);
+            select! {
+                result = ack => result.unwrap_or_else(|_| {
+                    Err("connection lost before the message was acknowledged".to_string())
+                }),
+                _ = timeout => {
+                    pending_acks.borrow_mut().remove(&id);
+                    Err("timed out waiting for server acknowledgement".to_string())
+                }
+            }
+        }
+    }
+
+    /// Registers interest in `room`'s incoming messages, returning a
+    /// `Receiver` that carries a copy of every raw frame tagged with that
+    /// room from here on — in addition to (not instead of) the usual
+    /// `EventBus` broadcast, so existing single-room listeners keep working
+    /// unchanged. Anything that arrived for `room` before this call, up to
+    /// [`MAX_ROOM_BUFFER`] messages, is replayed immediately.
+    pub fn subscribe(&self, room: &str) -> Receiver<String> {
+        let (mut tx, rx) = futures::channel::mpsc::channel(MAX_ROOM_BUFFER);
+        if let Some(buffered) = self.room_buffers.borrow_mut().remove(room) {
+            for msg in buffered {
+                let _ = tx.try_send(msg);
+            }
+        }
+        self.room_subscribers.borrow_mut().insert(room.to_string(), tx);
+        rx
+    }
+
+    /// Stops delivering `room`'s messages to whatever `subscribe`r was
+    /// registered for it; future messages for `room` are buffered again
+    /// instead of dropped.
+    pub fn unsubscribe(&self, room: &str) {
+        self.room_subscribers.borrow_mut().remove(room);
+    }
+
+    fn enqueue(&self, msg: String) {
+        let mut queue = self.queue.borrow_mut();
+        if queue.len() >= MAX_QUEUED_MESSAGES {
+            queue.pop_front();
+            drop(queue);
+            EventBus::dispatcher().send(Request::EventBusMsg(BusMessage::Frame(WebSocketMessage {
+                data: Some("outbound queue full, oldest message dropped".to_string()),
+                ..WebSocketMessage::new(MsgTypes::SendFailure)
+            })));
+        } else {
+            queue.push_back(msg);
+        }
+    }
+}
+
+/// Serializes `message` to the JSON text actually sent over the wire — the
+/// one place in the service (and the whole app) this happens, so callers
+/// pass a typed [`WebSocketMessage`] into `send`/`register`/`send_with_ack`
+/// instead of each re-serializing their own copy.
+fn to_wire(message: &WebSocketMessage) -> String {
+    serde_json::to_string(message).expect("WebSocketMessage always serializes")
+}
+
+/// Appends `token` to `url` as a `token` query parameter, percent-encoding
+/// it so a token containing `&` or `=` can't smuggle in extra parameters.
+fn append_token(url: &str, token: Option<&str>) -> String {
+    match token {
+        Some(t) if !t.is_empty() => {
+            let sep = if url.contains('?') { '&' } else { '?' };
+            let encoded = js_sys::encode_uri_component(t);
+            format!("{url}{sep}token={encoded}")
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// Keeps a connection to `url` alive for as long as the service lives,
+/// reconnecting with exponential backoff (plus jitter) whenever it drops.
+/// `in_rx` is owned here for the whole lifetime of the loop, so a dropped
+/// connection never loses the `tx` channel handed out to callers, and a
+/// burst of failures can only ever be retried by this single loop.
+async fn connect_loop(
+    url: String,
+    has_token: bool,
+    mut in_rx: Receiver<String>,
+    mut retry_rx: Receiver<()>,
+    mut policy: Box<dyn ReconnectPolicy>,
+    state: Rc<Cell<ConnectionState>>,
+    reconnect_attempt: Rc<Cell<u32>>,
+    queue: Queue,
+    pending_acks: PendingAcks,
+    room_subscribers: RoomSubscribers,
+    room_buffers: RoomBuffers,
+    compression_supported: Rc<Cell<bool>>,
+    metrics: Metrics,
+    register_payload: RegisterPayload,
+    ping_interval_ms: u32,
+) {
+    let mut fast_fail_streak = 0u32;
+
+    loop {
+        set_state(&state, ConnectionState::Connecting, reconnect_attempt.get());
+        let attempt_started_at = Date::now();
+
+        let ws = match WebSocket::open(&url) {
+            Ok(ws) => ws,
+            Err(e) => {
+                log::error!("failed to open websocket: {:?}", e);
+                if !wait_to_reconnect(policy.as_mut(), &state, &reconnect_attempt, &mut retry_rx).await {
+                    return;
+                }
+                continue;
+            }
+        };
 
-        let (in_tx, mut in_rx) = futures::channel::mpsc::channel::<String>(1000);
+        reconnect_attempt.set(0);
+        set_state(&state, ConnectionState::Open, 0);
+        compression_supported.set(false);
+        {
+            let mut m = metrics.borrow_mut();
+            m.bytes_sent = 0;
+            m.bytes_received = 0;
+            m.messages_sent = 0;
+            m.messages_received = 0;
+            m.last_latency_ms = None;
+            m.connected_at = Date::now();
+        }
+
+        let (mut write, mut read) = ws.split();
         let mut event_bus = EventBus::dispatcher();
+        let mut received_any = false;
+
+        // Announce that we can speak compressed `data` payloads; a server
+        // that doesn't recognize this frame will just ignore it, which is
+        // indistinguishable from "no" as far as `compression_supported` is
+        // concerned.
+        let capability = capability_frame();
+        record_sent(&metrics, capability.len());
+        if let Err(e) = write.send(Message::Text(capability)).await {
+            log::error!("failed to announce compression capability: {:?}", e);
+        }
+
+        // Re-announce who we are on every connection, not just the first —
+        // the server has no other way to tell "the same user reconnected"
+        // from "the user left" once this frame's channel drops.
+        if let Some(payload) = register_payload.borrow().clone() {
+            record_sent(&metrics, payload.len());
+            if let Err(e) = write.send(Message::Text(payload)).await {
+                log::error!("failed to resend registration: {:?}", e);
+            }
+        }
 
-        spawn_local(async move {
-            while let Some(s) = in_rx.next().await {
-                log::debug!("got event from channel! {}", s);
-                write.send(Message::Text(s)).await.unwrap();
+        // Flush anything that piled up while we were disconnected before
+        // handling any new traffic.
+        while let Some(queued) = queue.borrow_mut().pop_front() {
+            record_sent(&metrics, queued.len());
+            if let Err(e) = write.send(Message::Text(queued)).await {
+                log::error!("failed flushing queued message: {:?}", e);
+                break;
             }
-        });
+        }
 
-        spawn_local(async move {
-            while let Some(msg) = read.next().await {
-                match msg {
-                    Ok(Message::Text(data)) => {
-                        log::debug!("from websocket: {}", data);
-                        event_bus.send(Request::EventBusMsg(data));
+        let mut last_received_at = Date::now();
+        let mut last_ping_sent_at = Date::now();
+
+        loop {
+            let send_fut = in_rx.next().fuse();
+            let recv_fut = read.next().fuse();
+            let ping_fut = TimeoutFuture::new(ping_interval_ms).fuse();
+            pin_mut!(send_fut, recv_fut, ping_fut);
+
+            select! {
+                outgoing = send_fut => {
+                    match outgoing {
+                        Some(s) => {
+                            log::debug!("got event from channel! {}", s);
+                            record_sent(&metrics, s.len());
+                            if let Err(e) = write.send(Message::Text(s)).await {
+                                log::error!("ws send failed: {:?}", e);
+                                break;
+                            }
+                        }
+                        // The service was dropped; nothing left to reconnect for.
+                        None => return,
                     }
-                    Ok(Message::Bytes(b)) => {
-                        let decoded = std::str::from_utf8(&b);
-                        if let Ok(val) = decoded {
-                            log::debug!("from websocket: {}", val);
-                            event_bus.send(Request::EventBusMsg(val.into()));
+                }
+                incoming = recv_fut => {
+                    match incoming {
+                        Some(Ok(Message::Text(data))) => {
+                            received_any = true;
+                            last_received_at = Date::now();
+                            record_received(&metrics, data.len());
+                            if is_pong(&data) {
+                                metrics.borrow_mut().last_latency_ms =
+                                    Some((last_received_at - last_ping_sent_at).max(0.0) as u32);
+                            } else if is_capability_frame(&data) {
+                                log::debug!("server supports compressed payloads");
+                                compression_supported.set(true);
+                            } else if let Some(id) = extract_ack_id(&data) {
+                                if let Some(sender) = pending_acks.borrow_mut().remove(&id) {
+                                    let _ = sender.send(Ok(()));
+                                }
+                            } else {
+                                let data = maybe_decompress(&data);
+                                log::debug!("from websocket: {}", data);
+                                if let Some(room) = extract_room(&data) {
+                                    dispatch_to_room(&room_subscribers, &room_buffers, room, data.clone());
+                                }
+                                let bus_message = match protocol::parse_frame(&data) {
+                                    Ok(message) => BusMessage::Frame(message),
+                                    Err(e) => {
+                                        log::warn!("{}", e);
+                                        BusMessage::ParseError(e)
+                                    }
+                                };
+                                event_bus.send(Request::EventBusMsg(bus_message));
+                            }
                         }
+                        Some(Ok(Message::Bytes(b))) => {
+                            received_any = true;
+                            last_received_at = Date::now();
+                            record_received(&metrics, b.len());
+                            log::debug!("from websocket: {} byte binary frame", b.len());
+                            let message = WebSocketMessage {
+                                data: Some(base64::encode(&b)),
+                                ..WebSocketMessage::new(MsgTypes::Image)
+                            };
+                            event_bus.send(Request::EventBusMsg(BusMessage::Frame(message)));
+                        }
+                        Some(Err(e)) => {
+                            log::error!("ws: {:?}", e);
+                            break;
+                        }
+                        None => {
+                            log::debug!("WebSocket Closed");
+                            break;
+                        }
+                    }
+                }
+                _ = ping_fut => {
+                    if document_hidden() {
+                        // Pause the watchdog while backgrounded rather than
+                        // penalizing a tab for time it wasn't looking —
+                        // resets the clock so it gets a full window once
+                        // the tab is foregrounded again.
+                        last_received_at = Date::now();
+                    } else if Date::now() - last_received_at > (ping_interval_ms + READ_TIMEOUT_GRACE_MS) as f64 {
+                        log::warn!("no data received within timeout, reconnecting");
+                        emit_stalled();
+                        break;
                     }
-                    Err(e) => {
-                        log::error!("ws: {:?}", e)
+                    let ping = serde_json::json!({ "messageType": "ping" }).to_string();
+                    record_sent(&metrics, ping.len());
+                    last_ping_sent_at = Date::now();
+                    if let Err(e) = write.send(Message::Text(ping)).await {
+                        log::error!("ping send failed: {:?}", e);
+                        break;
                     }
+                    emit_metrics(&metrics, state.get());
                 }
             }
-            log::debug!("WebSocket Closed");
-        });
+        }
+
+        // Announce the drop as soon as it happens rather than leaving
+        // `Chat` showing `Open` until whatever `wait_to_reconnect` decides
+        // next (`Reconnecting` after a delay, or `Failed` once the policy
+        // gives up) — both of those are meaningful states in their own
+        // right, not stand-ins for "the socket just closed".
+        set_state(&state, ConnectionState::Closed, reconnect_attempt.get());
+
+        // Nobody's coming back to ack these on this connection; fail them
+        // now instead of letting them sit until their own timeout while
+        // the map quietly grows across reconnects.
+        for (_, sender) in pending_acks.borrow_mut().drain() {
+            let _ = sender.send(Err("connection lost before the message was acknowledged".to_string()));
+        }
+
+        if !received_any && Date::now() - attempt_started_at < FAST_FAIL_MS {
+            fast_fail_streak += 1;
+        } else {
+            fast_fail_streak = 0;
+        }
+
+        if has_token && fast_fail_streak >= MAX_FAST_FAILS {
+            log::error!(
+                "websocket closed instantly {} times in a row with a token configured; \
+                 treating this as a rejected handshake instead of retrying forever",
+                fast_fail_streak
+            );
+            set_state(&state, ConnectionState::Unauthorized, reconnect_attempt.get());
+            return;
+        }
+
+        if !wait_to_reconnect(policy.as_mut(), &state, &reconnect_attempt, &mut retry_rx).await {
+            return;
+        }
+    }
+}
+
+/// Delays via `policy` before the next connection attempt. Once it gives up
+/// (`next_delay` returns `None`), parks on `retry_rx` instead of returning,
+/// so a manual [`WebsocketService::retry`] can wake `connect_loop` back up
+/// without tearing down and recreating the whole service. Returns `false`
+/// only if the service itself was dropped while parked (`retry_tx` closed),
+/// in which case there's nothing left to retry for.
+async fn wait_to_reconnect(
+    policy: &mut dyn ReconnectPolicy,
+    state: &Rc<Cell<ConnectionState>>,
+    reconnect_attempt: &Rc<Cell<u32>>,
+    retry_rx: &mut Receiver<()>,
+) -> bool {
+    match policy.next_delay(reconnect_attempt.get()) {
+        Some(delay) => {
+            reconnect_attempt.set(reconnect_attempt.get() + 1);
+            set_state(state, ConnectionState::Reconnecting, reconnect_attempt.get());
+            sleep_with_jitter(delay).await;
+            true
+        }
+        None => {
+            log::error!(
+                "reconnect policy gave up after {} attempts, waiting for a manual retry",
+                reconnect_attempt.get()
+            );
+            set_state(state, ConnectionState::Failed, reconnect_attempt.get());
+            if retry_rx.next().await.is_none() {
+                return false;
+            }
+            reconnect_attempt.set(0);
+            true
+        }
+    }
+}
+
+/// Updates the shared state cell and lets the UI know via the `EventBus`,
+/// using the same `connectionstate` message type `Chat` decodes. `attempt`
+/// is only meaningful while `Reconnecting`, but is always sent so `Chat`
+/// can tell a first connect apart from a reconnect once it's `Open` again.
+fn set_state(state: &Rc<Cell<ConnectionState>>, new_state: ConnectionState, attempt: u32) {
+    state.set(new_state);
+    let message = WebSocketMessage {
+        data: Some(format!("{:?}", new_state).to_lowercase()),
+        attempt: Some(attempt),
+        ..WebSocketMessage::new(MsgTypes::ConnectionState)
+    };
+    EventBus::dispatcher().send(Request::EventBusMsg(BusMessage::Frame(message)));
+}
+
+/// Tallies an outgoing frame of `bytes` bytes against both the
+/// current-connection and cumulative-session counters in `metrics`.
+fn record_sent(metrics: &Metrics, bytes: usize) {
+    let mut m = metrics.borrow_mut();
+    m.messages_sent += 1;
+    m.bytes_sent += bytes as u64;
+    m.total_messages_sent += 1;
+    m.total_bytes_sent += bytes as u64;
+}
+
+/// Tallies an incoming frame of `bytes` bytes against both the
+/// current-connection and cumulative-session counters in `metrics`.
+fn record_received(metrics: &Metrics, bytes: usize) {
+    let mut m = metrics.borrow_mut();
+    m.messages_received += 1;
+    m.bytes_received += bytes as u64;
+    m.total_messages_received += 1;
+    m.total_bytes_received += bytes as u64;
+}
+
+/// Broadcasts the current `metrics` snapshot over the `EventBus` as a
+/// `MsgTypes::Metrics` frame, piggybacking on the keepalive ping's cadence
+/// rather than running its own timer.
+fn emit_metrics(metrics: &Metrics, state: ConnectionState) {
+    let snapshot = {
+        let m = metrics.borrow();
+        let uptime_ms = if state.is_open() { Date::now() - m.connected_at } else { 0.0 };
+        ConnectionMetrics {
+            bytes_sent: m.bytes_sent,
+            bytes_received: m.bytes_received,
+            messages_sent: m.messages_sent,
+            messages_received: m.messages_received,
+            last_latency_ms: m.last_latency_ms,
+            uptime_ms,
+            total_bytes_sent: m.total_bytes_sent,
+            total_bytes_received: m.total_bytes_received,
+            total_messages_sent: m.total_messages_sent,
+            total_messages_received: m.total_messages_received,
+        }
+    };
+    let message = WebSocketMessage {
+        data: Some(serde_json::to_string(&snapshot).expect("ConnectionMetrics always serializes")),
+        ..WebSocketMessage::new(MsgTypes::Metrics)
+    };
+    EventBus::dispatcher().send(Request::EventBusMsg(BusMessage::Frame(message)));
+}
+
+/// Whether the tab is currently in the background, per the Page
+/// Visibility API — used to pause the stall watchdog while hidden.
+fn document_hidden() -> bool {
+    window()
+        .and_then(|w| w.document())
+        .map(|d| d.hidden())
+        .unwrap_or(false)
+}
+
+/// Broadcasts a `MsgTypes::Stalled` frame over the `EventBus` when the read
+/// timeout watchdog force-closes a connection, so `Chat` can tell the user
+/// a reconnect is already underway instead of the status dot just
+/// silently sitting on "Connected" a beat too long.
+fn emit_stalled() {
+    EventBus::dispatcher().send(Request::EventBusMsg(BusMessage::Frame(WebSocketMessage::new(
+        MsgTypes::Stalled,
+    ))));
+}
+
+/// Whether an incoming frame is our own `{"messageType":"pong"}` keepalive
+/// reply rather than a real chat message that should reach the `EventBus`.
+fn is_pong(data: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(data)
+        .ok()
+        .and_then(|v| v.get("messageType").and_then(|t| t.as_str()).map(String::from))
+        .map(|t| t.eq_ignore_ascii_case("pong"))
+        .unwrap_or(false)
+}
+
+/// Extracts the `id` from an `{"messageType":"ack","id":"..."}` frame used
+/// to resolve [`WebsocketService::send_with_ack`] futures, so it can be
+/// filtered out of the `EventBus` traffic the same way a pong is.
+fn extract_ack_id(data: &str) -> Option<String> {
+    let v: serde_json::Value = serde_json::from_str(data).ok()?;
+    if v.get("messageType")?.as_str()? != "ack" {
+        return None;
+    }
+    v.get("id")?.as_str().map(String::from)
+}
+
+/// Builds the frame sent right after connecting to announce support for
+/// compressed `data` payloads; a server that supports it echoes the same
+/// shape back, which [`is_capability_frame`] recognizes.
+fn capability_frame() -> String {
+    serde_json::json!({
+        "messageType": "capability",
+        "data": COMPRESSION_CAPABILITY,
+    })
+    .to_string()
+}
+
+/// Whether an incoming frame is the server's echo of [`capability_frame`],
+/// meaning it understands compressed `data` payloads from here on.
+fn is_capability_frame(data: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(data)
+        .ok()
+        .and_then(|v| {
+            let message_type = v.get("messageType")?.as_str()?.eq_ignore_ascii_case("capability");
+            let capability = v.get("data")?.as_str()? == COMPRESSION_CAPABILITY;
+            Some(message_type && capability)
+        })
+        .unwrap_or(false)
+}
+
+/// Deflate-compresses and base64-encodes `data`.
+fn compress(data: &str) -> String {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data.as_bytes()).expect("writing to an in-memory buffer can't fail");
+    let compressed = encoder.finish().expect("writing to an in-memory buffer can't fail");
+    base64::encode(compressed)
+}
+
+/// Reverses [`compress`], failing with a message fit for `log::warn!` if
+/// `data` isn't valid base64 or doesn't inflate to valid UTF-8.
+fn decompress(data: &str) -> Result<String, String> {
+    let bytes = base64::decode(data).map_err(|e| format!("invalid base64: {}", e))?;
+    let mut decoder = DeflateDecoder::new(&bytes[..]);
+    let mut out = String::new();
+    decoder
+        .read_to_string(&mut out)
+        .map_err(|e| format!("invalid deflate stream: {}", e))?;
+    Ok(out)
+}
+
+/// Compresses `msg`'s `data` field in place and flags it as compressed, if
+/// `compression_supported` and `data` is over [`COMPRESSION_THRESHOLD_BYTES`].
+/// Returns `msg` unchanged (not just uncompressed — verbatim) if it's too
+/// small to bother, isn't supported yet, or doesn't parse as JSON with a
+/// string `data` field.
+fn maybe_compress(msg: &str, compression_supported: bool) -> String {
+    if !compression_supported {
+        return msg.to_string();
+    }
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(msg) else {
+        return msg.to_string();
+    };
+    let Some(data) = value.get("data").and_then(|d| d.as_str()) else {
+        return msg.to_string();
+    };
+    if data.len() < COMPRESSION_THRESHOLD_BYTES {
+        return msg.to_string();
+    }
+    let compressed = compress(data);
+    let Some(obj) = value.as_object_mut() else {
+        return msg.to_string();
+    };
+    obj.insert("data".to_string(), serde_json::Value::String(compressed));
+    obj.insert("compressed".to_string(), serde_json::Value::Bool(true));
+    value.to_string()
+}
+
+/// Reverses [`maybe_compress`] on an incoming frame — a no-op unless it
+/// carries `"compressed":true`, in which case `data` is decompressed and
+/// the flag is dropped before the frame goes any further.
+fn maybe_decompress(msg: &str) -> String {
+    let Ok(mut value) = serde_json::from_str::<serde_json::Value>(msg) else {
+        return msg.to_string();
+    };
+    if !value.get("compressed").and_then(|c| c.as_bool()).unwrap_or(false) {
+        return msg.to_string();
+    }
+    let Some(data) = value.get("data").and_then(|d| d.as_str()) else {
+        return msg.to_string();
+    };
+    let decompressed = match decompress(data) {
+        Ok(decompressed) => decompressed,
+        Err(e) => {
+            log::warn!("failed to decompress incoming message: {}", e);
+            return msg.to_string();
+        }
+    };
+    let Some(obj) = value.as_object_mut() else {
+        return msg.to_string();
+    };
+    obj.insert("data".to_string(), serde_json::Value::String(decompressed));
+    obj.remove("compressed");
+    value.to_string()
+}
+
+/// Extracts the `room` field carried on client-originated frames (see
+/// `WebSocketMessage::room` in `components::chat`), used to route incoming
+/// messages to a matching [`WebsocketService::subscribe`]r. `None` if the
+/// frame has no room, which is left to the ordinary `EventBus` broadcast.
+fn extract_room(data: &str) -> Option<String> {
+    serde_json::from_str::<serde_json::Value>(data)
+        .ok()?
+        .get("room")?
+        .as_str()
+        .map(String::from)
+}
+
+/// Delivers `msg` to `room`'s subscriber if one is registered, otherwise
+/// buffers it (capped at [`MAX_ROOM_BUFFER`]) for whoever subscribes next.
+fn dispatch_to_room(subscribers: &RoomSubscribers, buffers: &RoomBuffers, room: String, msg: String) {
+    let delivered = subscribers
+        .borrow()
+        .get(&room)
+        .map(|tx| tx.clone().try_send(msg.clone()).is_ok())
+        .unwrap_or(false);
+    if !delivered {
+        let mut buffers = buffers.borrow_mut();
+        let buffer = buffers.entry(room).or_insert_with(VecDeque::new);
+        if buffer.len() >= MAX_ROOM_BUFFER {
+            buffer.pop_front();
+        }
+        buffer.push_back(msg);
+    }
+}
+
+/// Sleeps for `base` plus up to 20% random jitter, so a fleet of clients
+/// that all dropped at once doesn't reconnect in lockstep.
+async fn sleep_with_jitter(base: Duration) {
+    let base_ms = base.as_millis() as u32;
+    let jitter = (js_sys::Math::random() * base_ms as f64 * 0.2) as u32;
+    TimeoutFuture::new(base_ms + jitter).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compress, decompress};
+
+    #[test]
+    fn round_trips_empty_string() {
+        assert_eq!(decompress(&compress("")).unwrap(), "");
+    }
+
+    #[test]
+    fn round_trips_ascii_text() {
+        let text = "the quick brown fox jumps over the lazy dog";
+        assert_eq!(decompress(&compress(text)).unwrap(), text);
+    }
+
+    #[test]
+    fn round_trips_non_ascii_text() {
+        let text = "héllo — 世界 🎉";
+        assert_eq!(decompress(&compress(text)).unwrap(), text);
+    }
 
-        Self { tx: in_tx }
+    #[test]
+    fn decompress_rejects_invalid_base64() {
+        assert!(decompress("not valid base64!!").is_err());
     }
 }